@@ -1,45 +1,204 @@
 #![allow(unreachable_pub)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Display};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
 
-use super::parsers::int32;
+use super::parsers::int64;
 
+use maplit::hashmap;
 use nom::anychar;
+use rayon::prelude::*;
+
+pub mod debugger;
+pub mod symbolic;
 
 type Reg = char;
 
+/// Integer type that registers can hold.
+pub trait Word: Copy + Debug + Display + Eq + From<i32> + FromStr + Send + Sync {
+    fn wrapping_add(self, other: Self) -> Self;
+    fn wrapping_mul(self, other: Self) -> Self;
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_mul(self, other: Self) -> Option<Self>;
+
+    /// Convert a literal, or `None` if it doesn't fit.
+    fn checked_from(value: i64) -> Option<Self>;
+    /// Convert a literal, keeping only the bits that fit.
+    fn wrapping_from(value: i64) -> Self;
+
+    /// Convert to a program offset, or `None` if it doesn't fit in an `isize`.
+    fn to_offset(self) -> Option<isize>;
+}
+
+macro_rules! impl_word {
+    ($($t:ty),*) => {
+        $(
+            impl Word for $t {
+                fn wrapping_add(self, other: Self) -> Self {
+                    <$t>::wrapping_add(self, other)
+                }
+
+                fn wrapping_mul(self, other: Self) -> Self {
+                    <$t>::wrapping_mul(self, other)
+                }
+
+                fn checked_add(self, other: Self) -> Option<Self> {
+                    <$t>::checked_add(self, other)
+                }
+
+                fn checked_mul(self, other: Self) -> Option<Self> {
+                    <$t>::checked_mul(self, other)
+                }
+
+                fn checked_from(value: i64) -> Option<Self> {
+                    <$t>::try_from(value).ok()
+                }
+
+                #[allow(trivial_numeric_casts)]
+                fn wrapping_from(value: i64) -> Self {
+                    value as $t
+                }
+
+                fn to_offset(self) -> Option<isize> {
+                    isize::try_from(self).ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_word!(i32, i64, i128);
+
+fn get<W: Word>(registers: &HashMap<Reg, W>, r: Reg) -> W {
+    registers.get(&r).cloned().unwrap_or_else(|| W::from(0))
+}
+
+/// How register arithmetic behaves on overflow.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Arithmetic {
+    Wrapping,
+    Checked,
+}
+
+impl Arithmetic {
+    fn add<W: Word>(self, a: W, b: W) -> Option<W> {
+        match self {
+            Arithmetic::Wrapping => Some(a.wrapping_add(b)),
+            Arithmetic::Checked => a.checked_add(b),
+        }
+    }
+
+    fn mul<W: Word>(self, a: W, b: W) -> Option<W> {
+        match self {
+            Arithmetic::Wrapping => Some(a.wrapping_mul(b)),
+            Arithmetic::Checked => a.checked_mul(b),
+        }
+    }
+
+    fn literal<W: Word>(self, value: i64) -> Option<W> {
+        match self {
+            Arithmetic::Wrapping => Some(W::wrapping_from(value)),
+            Arithmetic::Checked => W::checked_from(value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub arithmetic: Arithmetic,
+    /// Stop once this many values have been sent by `out`.
+    pub out_limit: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            arithmetic: Arithmetic::Wrapping,
+            out_limit: None,
+        }
+    }
+}
+
+/// Reason that a `Vm` stopped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Halt {
+    /// The program counter left the program.
+    Finished,
+    /// `out_limit` values were sent by `out`.
+    OutLimit,
+    /// `next_output` ran out of steps before anything was sent.
+    StepLimit,
+    /// An arithmetic instruction overflowed, or a literal didn't fit in a
+    /// register, in `Arithmetic::Checked` mode.
+    Overflow { pc: usize },
+}
+
+// Digits and signs start a literal, so they can't name a register.
 named!(reg<&str, Reg>,
-    call!(anychar)
+    verify!(anychar, |c: char| !c.is_ascii_digit() && c != '-')
 );
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Arg {
     Reg(Reg),
-    Val(i32),
+    Val(i64),
 }
 
 impl Arg {
-    fn value(self, regs: &HashMap<Reg, i32>) -> i32 {
+    /// The value of the argument, or `None` if it's a literal which doesn't
+    /// fit in a register.
+    fn value<W: Word>(self, regs: &HashMap<Reg, W>, arithmetic: Arithmetic) -> Option<W> {
         match self {
-            Arg::Reg(ref r) => *regs.get(r).unwrap_or(&0),
-            Arg::Val(v) => v,
+            Arg::Reg(r) => Some(get(regs, r)),
+            Arg::Val(v) => arithmetic.literal(v),
+        }
+    }
+}
+
+impl Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Arg::Reg(r) => write!(f, "{}", r),
+            Arg::Val(v) => write!(f, "{}", v),
         }
     }
 }
 
 named!(arg<&str, Arg>,
     alt!(
-        int32 => { |n| Arg::Val(n) } |
+        int64 => { |n| Arg::Val(n) } |
         reg => { |c| Arg::Reg(c) }
     )
 );
 
+// Toggling can leave values where a register is expected, so accept those
+// too in order to read snapshots back in.
 named!(pub instruction<&str, Instruction>,
     ws!(
         alt!(
-            do_parse!(tag!("cpy") >> x: arg >> y: reg >> (Instruction::Cpy(x, y))) |
-            do_parse!(tag!("inc") >> x: reg >> (Instruction::Inc(x))) |
-            do_parse!(tag!("dec") >> x: reg >> (Instruction::Dec(x))) |
+            do_parse!(tag!("cpy") >> x: arg >> y: arg >> (
+                match y {
+                    Arg::Reg(y) => Instruction::Cpy(x, y),
+                    y => Instruction::CpyInvalid(x, y),
+                }
+            )) |
+            do_parse!(tag!("inc") >> x: arg >> (
+                match x {
+                    Arg::Reg(x) => Instruction::Inc(x),
+                    x => Instruction::IncInvalid(x),
+                }
+            )) |
+            do_parse!(tag!("dec") >> x: arg >> (
+                match x {
+                    Arg::Reg(x) => Instruction::Dec(x),
+                    x => Instruction::DecInvalid(x),
+                }
+            )) |
             do_parse!(tag!("jnz") >> x: arg >> y: arg >> (Instruction::Jnz(x, y))) |
             do_parse!(tag!("tgl") >> x: reg >> (Instruction::Tgl(x))) |
             do_parse!(tag!("out") >> x: arg >> (Instruction::Out(x)))
@@ -47,12 +206,14 @@ named!(pub instruction<&str, Instruction>,
     )
 );
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Instruction {
     Cpy(Arg, Reg),
     CpyInvalid(Arg, Arg),
     Inc(Reg),
+    IncInvalid(Arg),
     Dec(Reg),
+    DecInvalid(Arg),
     Jnz(Arg, Arg),
     Tgl(Reg),
     Out(Arg),
@@ -61,7 +222,70 @@ pub enum Instruction {
     Nop,
 }
 
-fn optimize_add(instructions: &mut [Instruction]) {
+/// Formats as assembunny source, except for `Add`, `Mul` and `Nop` which
+/// don't exist in the language and are shown as `add x y`, `mul x y` and `nop`.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Cpy(x, y) => write!(f, "cpy {} {}", x, y),
+            Instruction::CpyInvalid(x, y) => write!(f, "cpy {} {}", x, y),
+            Instruction::Inc(x) => write!(f, "inc {}", x),
+            Instruction::IncInvalid(x) => write!(f, "inc {}", x),
+            Instruction::Dec(x) => write!(f, "dec {}", x),
+            Instruction::DecInvalid(x) => write!(f, "dec {}", x),
+            Instruction::Jnz(x, y) => write!(f, "jnz {} {}", x, y),
+            Instruction::Tgl(x) => write!(f, "tgl {}", x),
+            Instruction::Out(x) => write!(f, "out {}", x),
+            Instruction::Add(x, y) => write!(f, "add {} {}", x, y),
+            Instruction::Mul(x, y) => write!(f, "mul {} {}", x, y),
+            Instruction::Nop => write!(f, "nop"),
+        }
+    }
+}
+
+impl Instruction {
+    /// Return the instruction that `tgl` turns this one into.
+    ///
+    /// | Before      | After       |
+    /// |-------------|-------------|
+    /// | `inc x`     | `dec x`     |
+    /// | `dec x`     | `inc x`     |
+    /// | `tgl x`     | `inc x`     |
+    /// | `out x`     | `inc x`     |
+    /// | `jnz x y`   | `cpy x y`   |
+    /// | `cpy x y`   | `jnz x y`   |
+    ///
+    /// Toggling can produce instructions without a register to write to, like
+    /// `inc 1` or `cpy 1 2`. These are kept as the `*Invalid` variants, which
+    /// are skipped when executed but still toggle as above.
+    ///
+    /// `Add`, `Mul` and `Nop` only exist in optimized programs and return
+    /// `None`; `run` toggles the source instruction they replaced instead.
+    pub fn toggled(&self) -> Option<Instruction> {
+        let toggled = match *self {
+            Instruction::Inc(x) => Instruction::Dec(x),
+            Instruction::IncInvalid(x) => Instruction::DecInvalid(x),
+            Instruction::Dec(x) | Instruction::Tgl(x) => Instruction::Inc(x),
+            Instruction::Out(Arg::Reg(x)) => Instruction::Inc(x),
+            Instruction::DecInvalid(x) | Instruction::Out(x) => Instruction::IncInvalid(x),
+            Instruction::Jnz(x, Arg::Reg(y)) => Instruction::Cpy(x, y),
+            Instruction::Jnz(x, y) => Instruction::CpyInvalid(x, y),
+            Instruction::Cpy(x, y) => Instruction::Jnz(x, Arg::Reg(y)),
+            Instruction::CpyInvalid(x, y) => Instruction::Jnz(x, y),
+            Instruction::Add(_, _) | Instruction::Mul(_, _) | Instruction::Nop => return None,
+        };
+        Some(toggled)
+    }
+}
+
+/// Whether `pc` is inside the block `start..=end` but not at its start, where
+/// the padding left by an optimization would skip the rest of the block.
+fn in_block(pc: Option<usize>, start: usize, end: usize) -> bool {
+    matches!(pc, Some(pc) if start < pc && pc <= end)
+}
+
+fn optimize_add(instructions: &mut [Instruction], pc: Option<usize>) -> bool {
+    let mut deferred = false;
     for i in 2..instructions.len() {
         let inc_x = match instructions[i - 2] {
             Instruction::Inc(x) => x,
@@ -82,13 +306,20 @@ fn optimize_add(instructions: &mut [Instruction]) {
             continue;
         }
 
+        if in_block(pc, i - 2, i) {
+            deferred = true;
+            continue;
+        }
+
         instructions[i - 2] = Instruction::Add(dec_x, inc_x);
         instructions[i - 1] = Instruction::Cpy(Arg::Val(0), dec_x);
         instructions[i] = Instruction::Nop;
     }
+    deferred
 }
 
-fn optimize_mul(instructions: &mut [Instruction]) {
+fn optimize_mul(instructions: &mut [Instruction], pc: Option<usize>) -> bool {
+    let mut deferred = false;
     for i in 5..instructions.len() {
         let (cpy_x, cpy_y) = match instructions[i - 5] {
             Instruction::Cpy(Arg::Reg(x), y) => (x, y),
@@ -124,6 +355,11 @@ fn optimize_mul(instructions: &mut [Instruction]) {
             continue;
         }
 
+        if in_block(pc, i - 5, i) {
+            deferred = true;
+            continue;
+        }
+
         instructions[i - 5] = Instruction::Mul(cpy_x, jnz2_x);
         instructions[i - 4] = Instruction::Add(jnz2_x, inc_x);
         instructions[i - 3] = Instruction::Cpy(Arg::Val(0), jnz2_x);
@@ -131,93 +367,589 @@ fn optimize_mul(instructions: &mut [Instruction]) {
         instructions[i - 1] = Instruction::Nop;
         instructions[i] = Instruction::Nop;
     }
+    deferred
+}
+
+/// Replace common loops with `Add` and `Mul`.
+///
+/// Replaced instructions are padded with `Cpy` and `Nop` so that the result
+/// lines up with `source`. Loops with `pc` partway through them are left
+/// alone, and the returned flag says whether there were any.
+fn optimize(source: &[Instruction], pc: Option<usize>) -> (Vec<Instruction>, bool) {
+    let mut instructions = source.to_vec();
+    let deferred_mul = optimize_mul(&mut instructions, pc);
+    let deferred_add = optimize_add(&mut instructions, pc);
+    (instructions, deferred_mul || deferred_add)
 }
 
-pub fn run(
-    mut instructions: Vec<Instruction>,
-    registers: &mut HashMap<Reg, i32>,
-    out_limit: Option<u64>,
-) -> bool {
-    let mut i = 0;
-    let mut num_out: u64 = 0;
+/// A program along with its optimized form.
+#[derive(Clone, Debug)]
+pub struct Program {
+    source: Vec<Instruction>,
+    optimized: Vec<Instruction>,
+    /// Whether `optimized` needs redoing once the program counter moves,
+    /// either after a toggle or because a loop was left unoptimized.
+    stale: bool,
+}
+
+impl Program {
+    pub fn new(source: Vec<Instruction>) -> Self {
+        let (optimized, _) = optimize(&source, None);
+        Program { source, optimized, stale: false }
+    }
+
+    /// Fetch the instruction at `pc`, first re-optimizing around it if needed.
+    fn get(&mut self, pc: isize) -> Option<&Instruction> {
+        let pc = usize::try_from(pc).ok();
+        if self.stale {
+            let (optimized, deferred) = optimize(&self.source, pc);
+            self.optimized = optimized;
+            self.stale = deferred;
+        }
+        pc.and_then(move |i| self.optimized.get(i))
+    }
+
+    /// The unoptimized instructions, including any toggles so far.
+    pub fn source(&self) -> &[Instruction] {
+        &self.source
+    }
+
+    fn toggle(&mut self, i: usize) {
+        // Toggle the unoptimized instruction, as the one in `optimized` may
+        // be part of an optimized loop which no longer applies. Optimizing
+        // again waits for the next fetch, as a loop created by the toggle
+        // mustn't be replaced while the program counter is inside it.
+        if let Some(toggled) = self.source.get(i).and_then(Instruction::toggled) {
+            self.source[i] = toggled;
+            self.stale = true;
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Vm<W: Word> {
+    program: Program,
+    pub pc: isize,
+    pub registers: HashMap<Reg, W>,
+    pub output: Vec<W>,
+    /// Number of instructions executed.
+    pub steps: u64,
+    pub options: Options,
+}
+
+impl<W: Word> Vm<W> {
+    pub fn new(program: Program, registers: HashMap<Reg, W>, options: Options) -> Self {
+        Vm {
+            program,
+            pc: 0,
+            registers,
+            output: Vec::new(),
+            steps: 0,
+            options,
+        }
+    }
+
+    pub fn restore(snapshot: Snapshot<W>, options: Options) -> Self {
+        // Optimizing without a program counter gives the same loops as when
+        // the snapshot was taken, unless a toggle had left some waiting for
+        // the program counter to move on.
+        let mut program = Program::new(snapshot.program);
+        program.stale = snapshot.reoptimize;
+        Vm {
+            program,
+            pc: snapshot.pc,
+            registers: snapshot.registers.into_iter().collect(),
+            output: snapshot.output,
+            steps: snapshot.steps,
+            options,
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot<W> {
+        Snapshot {
+            pc: self.pc,
+            steps: self.steps,
+            reoptimize: self.program.stale,
+            registers: self.registers.iter().map(|(&r, &v)| (r, v)).collect(),
+            output: self.output.clone(),
+            program: self.program.source.clone(),
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
 
-    optimize_mul(&mut instructions);
-    optimize_add(&mut instructions);
+    fn set(&mut self, r: Reg, value: Option<W>) -> Result<(), Halt> {
+        let value = value.ok_or(Halt::Overflow { pc: self.pc as usize })?;
+        self.registers.insert(r, value);
+        Ok(())
+    }
 
-    let mut state = 1;
+    /// Execute a single instruction.
+    pub fn step(&mut self) -> Result<(), Halt> {
+        let instruction = self.program.get(self.pc).cloned().ok_or(Halt::Finished)?;
+        let arithmetic = self.options.arithmetic;
+        let registers = &self.registers;
+        let overflow = Halt::Overflow { pc: self.pc as usize };
+        let value = |arg: Arg| arg.value(registers, arithmetic).ok_or(overflow);
+        let mut next = self.pc + 1;
 
-    while let Some(instruction) = instructions.get(i as usize).cloned() {
         match instruction {
-            Instruction::Cpy(ref x, ref y) => {
-                *registers.entry(*y).or_insert(0) = x.value(registers);
+            Instruction::Cpy(x, y) => {
+                let value = value(x)?;
+                self.set(y, Some(value))?;
             }
-            Instruction::Inc(ref x) => {
-                *registers.entry(*x).or_insert(0) += 1;
+            Instruction::Inc(x) => {
+                let value = arithmetic.add(get(registers, x), W::from(1));
+                self.set(x, value)?;
             }
-            Instruction::Add(ref x, ref y) => {
-                *registers.entry(*y).or_insert(0) += *registers.get(x).unwrap_or(&0);
+            Instruction::Add(x, y) => {
+                let value = arithmetic.add(get(registers, y), get(registers, x));
+                self.set(y, value)?;
             }
-            Instruction::Mul(ref x, ref y) => {
-                *registers.entry(*y).or_insert(0) *= *registers.get(x).unwrap_or(&0);
+            Instruction::Mul(x, y) => {
+                let value = arithmetic.mul(get(registers, y), get(registers, x));
+                self.set(y, value)?;
             }
-            Instruction::Dec(ref x) => {
-                *registers.entry(*x).or_insert(0) -= 1;
+            Instruction::Dec(x) => {
+                let value = arithmetic.add(get(registers, x), W::from(-1));
+                self.set(x, value)?;
             }
-            Instruction::Jnz(ref x, ref y) => {
-                if x.value(registers) != 0 {
-                    // -1 to account for normal i += 1
-                    i += y.value(registers) - 1;
+            Instruction::Jnz(x, y) => {
+                if value(x)? != W::from(0) {
+                    next = value(y)?.to_offset()
+                        .and_then(|o| self.pc.checked_add(o))
+                        .ok_or(Halt::Finished)?;
                 }
             }
-            Instruction::Out(ref x) => {
-                num_out += 1;
-                let want = match state {
-                    0 => 1,
-                    1 => 0,
-                    _ => unreachable!("0 or 1"),
-                };
-                if x.value(registers) != want {
-                    return false;
-                }
-                state = want;
+            Instruction::Out(x) => {
+                let value = value(x)?;
+                self.output.push(value);
             }
-            Instruction::Tgl(ref x) => {
-                let toggle_idx = (i + *registers.get(x).unwrap_or(&0)) as usize;
-                if toggle_idx < instructions.len() {
-                    match instructions[toggle_idx].clone() {
-                        Instruction::Inc(p) => {
-                            instructions[toggle_idx] = Instruction::Dec(p);
-                        }
-                        Instruction::Dec(p) | Instruction::Tgl(p) => {
-                            instructions[toggle_idx] = Instruction::Inc(p);
-                        }
-                        Instruction::Out(_) => unimplemented!(),
-                        Instruction::Jnz(p, q) => match q {
-                            Arg::Reg(r) => {
-                                instructions[toggle_idx] = Instruction::Cpy(p, r);
-                            }
-                            Arg::Val(_) => {
-                                instructions[toggle_idx] = Instruction::CpyInvalid(p, q);
-                            }
-                        },
-                        Instruction::Cpy(p, q) => {
-                            instructions[toggle_idx] = Instruction::Jnz(p, Arg::Reg(q));
-                        }
-                        Instruction::CpyInvalid(p, q) => {
-                            instructions[toggle_idx] = Instruction::Jnz(p, q);
-                        }
-                        Instruction::Nop | Instruction::Add(_, _) | Instruction::Mul(_, _) => {}
-                    }
+            Instruction::Tgl(x) => {
+                let toggle_idx = get(registers, x).to_offset()
+                    .and_then(|o| self.pc.checked_add(o))
+                    .and_then(|i| usize::try_from(i).ok());
+                if let Some(i) = toggle_idx {
+                    self.program.toggle(i);
                 }
             }
-            Instruction::Nop | Instruction::CpyInvalid(_, _) => {}
+            Instruction::Nop
+            | Instruction::CpyInvalid(_, _)
+            | Instruction::IncInvalid(_)
+            | Instruction::DecInvalid(_) => {}
         };
-        i += 1;
-        if let Some(limit) = out_limit {
-            if num_out > limit {
-                return true;
+        self.pc = next;
+        self.steps += 1;
+
+        if let Some(limit) = self.options.out_limit {
+            if self.output.len() as u64 >= limit {
+                return Err(Halt::OutLimit);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run until the program halts.
+    pub fn run(&mut self) -> Halt {
+        loop {
+            if let Err(halt) = self.step() {
+                return halt;
+            }
+        }
+    }
+
+    /// Run until the next value is sent by `out`, giving up after `max_steps`.
+    pub fn next_output(&mut self, max_steps: u64) -> Result<W, Halt> {
+        let num_out = self.output.len();
+        for _ in 0..max_steps {
+            self.step()?;
+            if self.output.len() > num_out {
+                return Ok(self.output[num_out]);
+            }
+        }
+        Err(Halt::StepLimit)
+    }
+}
+
+/// Complete state of a `Vm`, apart from its options.
+///
+/// The text format written by `save` looks like:
+///
+/// ```text
+/// pc 2
+/// steps 2
+/// reoptimize false
+/// registers a=41
+/// output
+/// program
+/// cpy 41 a
+/// inc a
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot<W> {
+    pub pc: isize,
+    pub steps: u64,
+    /// Whether a toggle left the program to be optimized again once the
+    /// program counter moves, see `Program::get`.
+    pub reoptimize: bool,
+    pub registers: BTreeMap<Reg, W>,
+    pub output: Vec<W>,
+    pub program: Vec<Instruction>,
+}
+
+impl<W: Word> Snapshot<W> {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<W: Word> Display for Snapshot<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pc {}", self.pc)?;
+        writeln!(f, "steps {}", self.steps)?;
+        writeln!(f, "reoptimize {}", self.reoptimize)?;
+        write!(f, "registers")?;
+        for (r, v) in &self.registers {
+            write!(f, " {}={}", r, v)?;
+        }
+        write!(f, "\noutput")?;
+        for v in &self.output {
+            write!(f, " {}", v)?;
+        }
+        writeln!(f, "\nprogram")?;
+        for instruction in &self.program {
+            writeln!(f, "{}", instruction)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Word> FromStr for Snapshot<W> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn field<'a>(lines: &mut dyn Iterator<Item = &'a str>, name: &str) -> Result<Vec<&'a str>, String> {
+            let line = lines.next().ok_or_else(|| format!("missing {}", name))?;
+            let mut words = line.split_whitespace();
+            if words.next() != Some(name) {
+                return Err(format!("expected {}, got {:?}", name, line));
             }
+            Ok(words.collect())
+        }
+
+        fn value<T: FromStr>(s: &str) -> Result<T, String> {
+            s.parse().map_err(|_| format!("invalid value {:?}", s))
         }
+
+        let mut lines = s.lines();
+
+        let pc = match field(&mut lines, "pc")?.as_slice() {
+            [pc] => value(pc)?,
+            _ => return Err("expected one pc".to_string()),
+        };
+
+        let steps = match field(&mut lines, "steps")?.as_slice() {
+            [steps] => value(steps)?,
+            _ => return Err("expected one step count".to_string()),
+        };
+
+        let reoptimize = match field(&mut lines, "reoptimize")?.as_slice() {
+            [reoptimize] => value(reoptimize)?,
+            _ => return Err("expected reoptimize to be true or false".to_string()),
+        };
+
+        let registers = field(&mut lines, "registers")?.into_iter()
+            .map(|s| {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(r), Some('=')) => Ok((r, value(chars.as_str())?)),
+                    _ => Err(format!("invalid register {:?}", s)),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        let output = field(&mut lines, "output")?.into_iter()
+            .map(value)
+            .collect::<Result<_, _>>()?;
+
+        field(&mut lines, "program")?;
+        let program = lines
+            .map(|l| {
+                instruction(l).to_result()
+                    .map_err(|_| format!("invalid instruction {:?}", l))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Snapshot { pc, steps, reoptimize, registers, output, program })
+    }
+}
+
+pub fn run<W: Word>(
+    instructions: Vec<Instruction>,
+    registers: &mut HashMap<Reg, W>,
+    options: Options,
+) -> Halt {
+    let mut vm = Vm::new(Program::new(instructions), registers.clone(), options);
+    let halt = vm.run();
+    *registers = vm.registers;
+    halt
+}
+
+/// Find the smallest positive value of `register` for which `predicate`
+/// accepts a VM started with it and `options`.
+///
+/// Candidates are tried in parallel batches, so the predicate should bound how
+/// far it steps the VM, e.g. with `Vm::next_output`, or one candidate which
+/// never halts stalls the whole search.
+pub fn find_min_input<W, F>(program: &Program, register: Reg, options: Options, predicate: F) -> Option<W>
+where
+    W: Word,
+    F: Fn(&mut Vm<W>) -> bool + Sync,
+{
+    let batch_size = rayon::current_num_threads() as i32 * 16;
+    let test = |a: W| {
+        let registers = hashmap!{register => a};
+        let mut vm = Vm::new(program.clone(), registers, options);
+        predicate(&mut vm)
+    };
+
+    let mut start = W::from(1);
+    loop {
+        let found = (0..batch_size).into_par_iter()
+            .filter_map(|i| start.checked_add(W::from(i)))
+            .find_first(|&a| test(a));
+        if found.is_some() {
+            return found;
+        }
+        start = start.checked_add(W::from(batch_size))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) fn parse(source: &str) -> Vec<Instruction> {
+        source.lines()
+            .map(|l| instruction(l).to_result().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_inc_overflow() {
+        let instructions = parse("cpy 2147483647 a\ninc a");
+        let checked = Options { arithmetic: Arithmetic::Checked, ..Default::default() };
+
+        let mut registers: HashMap<Reg, i32> = HashMap::new();
+        assert_eq!(run(instructions.clone(), &mut registers, checked), Halt::Overflow { pc: 1 });
+        assert_eq!(registers[&'a'], i32::MAX);
+
+        let mut registers: HashMap<Reg, i32> = HashMap::new();
+        assert_eq!(run(instructions.clone(), &mut registers, Default::default()), Halt::Finished);
+        assert_eq!(registers[&'a'], i32::MIN);
+
+        let mut registers: HashMap<Reg, i64> = HashMap::new();
+        assert_eq!(run(instructions, &mut registers, checked), Halt::Finished);
+        assert_eq!(registers[&'a'], 2_147_483_648);
+    }
+
+    #[test]
+    fn test_wide_literal() {
+        let instructions = parse("cpy 3000000000 a");
+        let checked = Options { arithmetic: Arithmetic::Checked, ..Default::default() };
+
+        let mut registers: HashMap<Reg, i64> = HashMap::new();
+        assert_eq!(run(instructions.clone(), &mut registers, checked), Halt::Finished);
+        assert_eq!(registers[&'a'], 3_000_000_000);
+
+        let mut registers: HashMap<Reg, i32> = HashMap::new();
+        assert_eq!(run(instructions.clone(), &mut registers, checked), Halt::Overflow { pc: 0 });
+        assert!(registers.is_empty());
+
+        let mut registers: HashMap<Reg, i32> = HashMap::new();
+        assert_eq!(run(instructions, &mut registers, Default::default()), Halt::Finished);
+        assert_eq!(registers[&'a'], 3_000_000_000u32 as i32);
+
+        assert!(instruction("cpy 9223372036854775808 a").to_result().is_err());
+    }
+
+    #[test]
+    fn test_mul_overflow() {
+        // a += b * d, which the optimizer turns into a Mul at pc 2.
+        let instructions = parse("\
+            cpy 100000 b\n\
+            cpy 100000 d\n\
+            cpy b c\n\
+            inc a\n\
+            dec c\n\
+            jnz c -2\n\
+            dec d\n\
+            jnz d -5");
+        let checked = Options { arithmetic: Arithmetic::Checked, ..Default::default() };
+
+        let mut registers: HashMap<Reg, i32> = HashMap::new();
+        assert_eq!(run(instructions.clone(), &mut registers, checked), Halt::Overflow { pc: 2 });
+
+        let mut registers = hashmap!{'a' => 0i128};
+        assert_eq!(run(instructions, &mut registers, checked), Halt::Finished);
+        assert_eq!(registers[&'a'], 10_000_000_000);
+    }
+
+    #[test]
+    fn test_toggle_one_argument() {
+        use self::Instruction::*;
+
+        assert_eq!(Inc('a').toggled(), Some(Dec('a')));
+        assert_eq!(Dec('a').toggled(), Some(Inc('a')));
+        assert_eq!(Tgl('a').toggled(), Some(Inc('a')));
+        assert_eq!(Out(Arg::Reg('a')).toggled(), Some(Inc('a')));
+        assert_eq!(Out(Arg::Val(1)).toggled(), Some(IncInvalid(Arg::Val(1))));
+        assert_eq!(IncInvalid(Arg::Val(1)).toggled(), Some(DecInvalid(Arg::Val(1))));
+        assert_eq!(DecInvalid(Arg::Val(1)).toggled(), Some(IncInvalid(Arg::Val(1))));
+    }
+
+    #[test]
+    fn test_toggle_two_arguments() {
+        use self::Instruction::*;
+
+        assert_eq!(Jnz(Arg::Val(1), Arg::Reg('b')).toggled(), Some(Cpy(Arg::Val(1), 'b')));
+        assert_eq!(
+            Jnz(Arg::Val(1), Arg::Val(2)).toggled(),
+            Some(CpyInvalid(Arg::Val(1), Arg::Val(2)))
+        );
+        assert_eq!(Cpy(Arg::Reg('a'), 'b').toggled(), Some(Jnz(Arg::Reg('a'), Arg::Reg('b'))));
+        assert_eq!(
+            CpyInvalid(Arg::Val(1), Arg::Val(2)).toggled(),
+            Some(Jnz(Arg::Val(1), Arg::Val(2)))
+        );
+    }
+
+    #[test]
+    fn test_toggle_synthetic() {
+        use self::Instruction::*;
+
+        assert_eq!(Add('a', 'b').toggled(), None);
+        assert_eq!(Mul('a', 'b').toggled(), None);
+        assert_eq!(Nop.toggled(), None);
+    }
+
+    #[test]
+    fn test_tgl_example() {
+        let instructions = parse("\
+            cpy 2 a\n\
+            tgl a\n\
+            tgl a\n\
+            tgl a\n\
+            cpy 1 a\n\
+            dec a\n\
+            dec a");
+        let mut registers: HashMap<Reg, i32> = HashMap::new();
+        assert_eq!(run(instructions, &mut registers, Default::default()), Halt::Finished);
+        assert_eq!(registers[&'a'], 3);
+    }
+
+    #[test]
+    fn test_tgl_out() {
+        // The out becomes inc a, so a is incremented instead of sent.
+        let instructions = parse("tgl b\nout a");
+        let mut registers = hashmap!{'b' => 1};
+        assert_eq!(run(instructions, &mut registers, Default::default()), Halt::Finished);
+        assert_eq!(registers[&'a'], 1);
+    }
+
+    #[test]
+    fn test_tgl_optimized() {
+        // Without the tgl, lines 2-4 would be optimized to a += b. Toggling
+        // the inc makes it a -= b instead.
+        let instructions = parse("\
+            cpy 3 b\n\
+            tgl c\n\
+            inc a\n\
+            dec b\n\
+            jnz b -2");
+        let mut registers = hashmap!{'c' => 1};
+        assert_eq!(run(instructions, &mut registers, Default::default()), Halt::Finished);
+        assert_eq!(registers[&'a'], -3);
+    }
+
+    #[test]
+    fn test_tgl_into_loop() {
+        // The tgl turns itself into inc a, completing an add loop while the
+        // program counter is already inside it, so the loop has to run as
+        // written rather than being optimized to a += b.
+        let instructions = parse("\
+            cpy 3 b\n\
+            cpy 0 a\n\
+            tgl a\n\
+            dec b\n\
+            jnz b -2");
+        let mut registers: HashMap<Reg, i32> = HashMap::new();
+        assert_eq!(run(instructions, &mut registers, Default::default()), Halt::Finished);
+        assert_eq!(registers[&'a'], 2);
+        assert_eq!(registers[&'b'], 0);
+    }
+
+    #[test]
+    fn test_find_min_input() {
+        // Sends a - 5 and then halts.
+        let program = Program::new(parse("dec a\ndec a\ndec a\ndec a\ndec a\nout a"));
+        let a = find_min_input(&program, 'a', Default::default(), |vm: &mut Vm<i64>| {
+            vm.next_output(100) == Ok(100)
+        });
+        assert_eq!(a, Some(105));
+
+        // Loops forever without sending anything when a is 1, and sends 1
+        // when a is 2.
+        let program = Program::new(parse("cpy a b\ndec b\njnz b 2\njnz 1 0\nout b"));
+        let a = find_min_input(&program, 'a', Default::default(), |vm: &mut Vm<i64>| {
+            vm.next_output(100) == Ok(1)
+        });
+        assert_eq!(a, Some(2));
+
+        // b = 2147483640 + a, which only overflows with checked arithmetic.
+        let program = Program::new(parse("cpy 2147483640 b\ncpy a c\ninc b\ndec c\njnz c -2"));
+        let checked = Options { arithmetic: Arithmetic::Checked, ..Default::default() };
+        let a = find_min_input(&program, 'a', checked, |vm: &mut Vm<i32>| {
+            matches!(vm.run(), Halt::Overflow { .. })
+        });
+        assert_eq!(a, Some(8));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let source = "cpy 3 a\ntgl a\nout a\ncpy 1 2\njnz 1 1\ninc 5";
+        let mut vm: Vm<i64> = Vm::new(Program::new(parse(source)), HashMap::new(), Default::default());
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+
+        let snapshot = vm.snapshot();
+        assert_eq!(snapshot.to_string(), "\
+            pc 3\n\
+            steps 3\n\
+            reoptimize false\n\
+            registers a=3\n\
+            output 3\n\
+            program\n\
+            cpy 3 a\n\
+            tgl a\n\
+            out a\n\
+            cpy 1 2\n\
+            cpy 1 1\n\
+            inc 5\n");
+        assert_eq!(snapshot.to_string().parse(), Ok(snapshot.clone()));
+
+        let mut restored = Vm::restore(snapshot, Default::default());
+        assert_eq!(restored.run(), Halt::Finished);
+        assert_eq!(restored.registers, hashmap!{'a' => 3});
+        assert_eq!(restored.output, vec![3]);
     }
-    true
 }
@@ -0,0 +1,282 @@
+//! Step a `Vm` backwards as well as forwards, to see how self-modifying
+//! programs change over time.
+
+use std::io::{self, BufRead, Write};
+
+use super::{Halt, Snapshot, Vm, Word};
+
+const HELP: &str = "\
+s [n]      step forwards n instructions
+b [n]      step backwards n instructions
+t          run until tgl next changes the program
+T          go back to before tgl last changed the program
+c          continue until the program halts
+p          print the program
+save FILE  save a snapshot
+load FILE  load a snapshot
+q          quit";
+
+#[derive(Debug)]
+pub struct Debugger<W: Word> {
+    vm: Vm<W>,
+    /// Snapshots taken every `interval` steps, oldest first.
+    snapshots: Vec<Snapshot<W>>,
+    interval: u64,
+}
+
+impl<W: Word> Debugger<W> {
+    pub fn new(vm: Vm<W>, interval: u64) -> Self {
+        let snapshots = vec![vm.snapshot()];
+        Debugger { vm, snapshots, interval }
+    }
+
+    /// Replace the VM with a restored snapshot, forgetting its history.
+    pub fn load(&mut self, snapshot: Snapshot<W>) {
+        self.vm = Vm::restore(snapshot, self.vm.options);
+        self.snapshots = vec![self.vm.snapshot()];
+    }
+
+    pub fn step(&mut self) -> Result<(), Halt> {
+        let result = self.vm.step();
+        let last = self.snapshots.last().map_or(0, |s| s.steps);
+        if self.vm.steps.is_multiple_of(self.interval) && self.vm.steps > last {
+            self.snapshots.push(self.vm.snapshot());
+        }
+        result
+    }
+
+    /// Run until the program halts, taking snapshots along the way.
+    pub fn run(&mut self) -> Halt {
+        loop {
+            if let Err(halt) = self.step() {
+                return halt;
+            }
+        }
+    }
+
+    /// Go back one step by restoring the latest snapshot before it and
+    /// executing forwards again. Returns `false` if there is no history.
+    pub fn step_back(&mut self) -> bool {
+        if self.vm.steps <= self.snapshots[0].steps {
+            return false;
+        }
+        let target = self.vm.steps - 1;
+
+        while self.snapshots.len() > 1 && self.snapshots[self.snapshots.len() - 1].steps > target {
+            self.snapshots.pop();
+        }
+        let snapshot = self.snapshots[self.snapshots.len() - 1].clone();
+        self.vm = Vm::restore(snapshot, self.vm.options);
+
+        while self.vm.steps < target {
+            // OutLimit still executes the instruction. Anything else means
+            // the replay has gone differently, which mustn't loop forever.
+            match self.vm.step() {
+                Ok(()) | Err(Halt::OutLimit) => {}
+                Err(_) => break,
+            }
+        }
+        true
+    }
+
+    /// Run until `tgl` changes the program.
+    pub fn next_toggle(&mut self) -> Result<(), Halt> {
+        let program = self.vm.program().source().to_vec();
+        while self.vm.program().source() == &program[..] {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Go back to just before `tgl` last changed the program. Returns
+    /// `false` if it hasn't changed.
+    pub fn previous_toggle(&mut self) -> bool {
+        let program = self.vm.program().source().to_vec();
+        while self.step_back() {
+            if self.vm.program().source() != &program[..] {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn print_status<O: Write>(&self, output: &mut O) -> io::Result<()> {
+        let vm = &self.vm;
+        let instruction = vm.program().source().get(vm.pc as usize)
+            .map_or("-".to_string(), |i| i.to_string());
+        let mut registers = vm.registers.iter().collect::<Vec<_>>();
+        registers.sort_by_key(|&(r, _)| r);
+        let registers = registers.iter()
+            .map(|(r, v)| format!("{}={}", r, v))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(output, "step {}, pc {}: {}", vm.steps, vm.pc, instruction)?;
+        writeln!(output, "  registers: {}", registers)?;
+        if !vm.output.is_empty() {
+            let sent = vm.output.iter().map(W::to_string).collect::<Vec<_>>();
+            writeln!(output, "  output: {}", sent.join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// Read commands from `input` until it ends or `q` is entered.
+    pub fn repl<R: BufRead, O: Write>(&mut self, input: R, mut output: O) -> io::Result<()> {
+        writeln!(output, "{}", HELP)?;
+        self.print_status(&mut output)?;
+
+        for line in input.lines() {
+            let line = line?;
+            let mut words = line.split_whitespace();
+            let command = words.next();
+            let argument = words.next();
+            let count = argument.and_then(|n| n.parse().ok()).unwrap_or(1);
+
+            let halt = match command {
+                Some("s") => (0..count).map(|_| self.step()).find(Result::is_err),
+                Some("b") => {
+                    if !(0..count).all(|_| self.step_back()) {
+                        writeln!(output, "At the start of history")?;
+                    }
+                    None
+                }
+                Some("t") => Some(self.next_toggle()),
+                Some("T") => {
+                    if !self.previous_toggle() {
+                        writeln!(output, "No earlier toggle")?;
+                    }
+                    None
+                }
+                Some("c") => Some(Err(self.run())),
+                Some("p") => {
+                    for (i, instruction) in self.vm.program().source().iter().enumerate() {
+                        writeln!(output, "{:4} {}", i, instruction)?;
+                    }
+                    None
+                }
+                Some("save") => {
+                    match argument {
+                        Some(path) => self.vm.snapshot().save(path)?,
+                        None => writeln!(output, "Missing file name")?,
+                    }
+                    None
+                }
+                Some("load") => {
+                    match argument.map(Snapshot::load) {
+                        Some(Ok(snapshot)) => self.load(snapshot),
+                        Some(Err(e)) => writeln!(output, "Could not load snapshot: {}", e)?,
+                        None => writeln!(output, "Missing file name")?,
+                    }
+                    None
+                }
+                Some("q") => return Ok(()),
+                _ => {
+                    writeln!(output, "{}", HELP)?;
+                    None
+                }
+            };
+
+            if let Some(Err(halt)) = halt {
+                writeln!(output, "Halted: {:?}", halt)?;
+            }
+            self.print_status(&mut output)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::assembunny::tests::parse;
+    use crate::assembunny::{Instruction, Program};
+
+    fn debugger(source: &str) -> Debugger<i32> {
+        let vm = Vm::new(Program::new(parse(source)), HashMap::new(), Default::default());
+        Debugger::new(vm, 3)
+    }
+
+    fn debugger_from(snapshot: Snapshot<i32>) -> Debugger<i32> {
+        let text = snapshot.to_string();
+        let vm = Vm::restore(text.parse().unwrap(), Default::default());
+        Debugger::new(vm, 3)
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut debugger = debugger("inc a\ninc a\ninc a\ninc a\ninc a\ninc a\ninc a");
+        for _ in 0..7 {
+            debugger.step().unwrap();
+        }
+        assert_eq!(debugger.step(), Err(Halt::Finished));
+
+        for steps in (0..7).rev() {
+            assert!(debugger.step_back());
+            assert_eq!(debugger.vm.steps, steps);
+            assert_eq!(debugger.vm.pc, steps as isize);
+            assert_eq!(debugger.vm.registers.get(&'a').cloned().unwrap_or(0), steps as i32);
+        }
+        assert!(!debugger.step_back());
+    }
+
+    #[test]
+    fn test_step_back_optimized() {
+        // a = b * d, where lines 2-7 are optimized into a Mul whose padding
+        // the snapshots land in.
+        let mut debugger = debugger("\
+            cpy 4 b\n\
+            cpy 5 d\n\
+            cpy b c\n\
+            inc a\n\
+            dec c\n\
+            jnz c -2\n\
+            dec d\n\
+            jnz d -5");
+        let mut trace = vec![debugger.vm.snapshot()];
+        while debugger.step().is_ok() {
+            trace.push(debugger.vm.snapshot());
+        }
+        assert_eq!(debugger.vm.registers[&'a'], 20);
+
+        // The halting step doesn't count, so the last snapshot is where
+        // stepping back starts from.
+        for expected in trace.iter().rev().skip(1) {
+            assert!(debugger.step_back());
+            assert_eq!(debugger.vm.snapshot(), *expected);
+        }
+        assert!(!debugger.step_back());
+
+        // Loading a snapshot taken inside the Mul finishes the same way.
+        let mut debugger = debugger_from(trace[4].clone());
+        assert_eq!(debugger.run(), Halt::Finished);
+        assert_eq!(debugger.vm.registers, vec![('a', 20), ('b', 4), ('c', 0), ('d', 0)].into_iter().collect());
+    }
+
+    #[test]
+    fn test_run() {
+        let mut debugger = debugger("inc a\ninc a\ninc a\ninc a\ninc a\ninc a\ninc a");
+        assert_eq!(debugger.run(), Halt::Finished);
+        let steps = debugger.snapshots.iter().map(|s| s.steps).collect::<Vec<_>>();
+        assert_eq!(steps, vec![0, 3, 6]);
+
+        assert!(debugger.step_back());
+        assert_eq!(debugger.vm.steps, 6);
+        assert_eq!(debugger.snapshots.len(), 3);
+    }
+
+    #[test]
+    fn test_toggles() {
+        let mut debugger = debugger("cpy 2 a\ntgl a\ninc b\ninc a\ninc b");
+        debugger.next_toggle().unwrap();
+        assert_eq!(debugger.vm.steps, 2);
+        assert_eq!(debugger.vm.program().source()[3], Instruction::Dec('a'));
+
+        assert_eq!(debugger.step(), Ok(()));
+        assert!(debugger.previous_toggle());
+        assert_eq!(debugger.vm.pc, 1);
+        assert_eq!(debugger.vm.program().source()[3], Instruction::Inc('a'));
+        assert!(!debugger.previous_toggle());
+    }
+}
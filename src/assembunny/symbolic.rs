@@ -0,0 +1,335 @@
+//! Symbolic execution of programs with one unknown input register.
+
+use std::collections::HashMap;
+
+use super::{Arg, Instruction, Program, Reg, Vm};
+
+/// Give up on loops that run longer than this.
+const MAX_STEPS: usize = 10_000_000;
+
+/// `coeff * x + constant`, where `x` is the unknown input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Affine {
+    pub coeff: i64,
+    pub constant: i64,
+}
+
+impl Affine {
+    fn constant(constant: i64) -> Self {
+        Affine { coeff: 0, constant }
+    }
+
+    fn unknown() -> Self {
+        Affine { coeff: 1, constant: 0 }
+    }
+
+    fn as_constant(self) -> Option<i64> {
+        if self.coeff == 0 {
+            Some(self.constant)
+        } else {
+            None
+        }
+    }
+
+    fn add(self, other: Affine) -> Option<Affine> {
+        Some(Affine {
+            coeff: self.coeff.checked_add(other.coeff)?,
+            constant: self.constant.checked_add(other.constant)?,
+        })
+    }
+
+    /// Multiply, or `None` if the result isn't affine.
+    fn mul(self, other: Affine) -> Option<Affine> {
+        let (k, a) = match (self.as_constant(), other.as_constant()) {
+            (Some(k), _) => (k, other),
+            (_, Some(k)) => (k, self),
+            (None, None) => return None,
+        };
+        Some(Affine {
+            coeff: a.coeff.checked_mul(k)?,
+            constant: a.constant.checked_mul(k)?,
+        })
+    }
+
+    fn eval(self, x: i64) -> Option<i64> {
+        self.coeff.checked_mul(x)?.checked_add(self.constant)
+    }
+}
+
+fn value(registers: &HashMap<Reg, Affine>, arg: Arg) -> Affine {
+    match arg {
+        Arg::Reg(r) => registers.get(&r).cloned().unwrap_or_else(|| Affine::constant(0)),
+        Arg::Val(v) => Affine::constant(v),
+    }
+}
+
+/// Run `program` with `input` unknown until the program counter reaches
+/// `until`, and return the registers at that point.
+///
+/// Returns `None` if a jump, toggle or output depends on the unknown input,
+/// since the path taken can't be known.
+pub fn run_until(program: &Program, input: Reg, until: isize) -> Option<HashMap<Reg, Affine>> {
+    let mut program = program.clone();
+    let mut registers = HashMap::new();
+    registers.insert(input, Affine::unknown());
+    let mut pc = 0;
+
+    for _ in 0..MAX_STEPS {
+        if pc == until {
+            return Some(registers);
+        }
+        let instruction = program.get(pc)?.clone();
+        let mut next = pc + 1;
+
+        match instruction {
+            Instruction::Cpy(x, y) => {
+                let x = value(&registers, x);
+                registers.insert(y, x);
+            }
+            Instruction::Inc(x) => {
+                let incremented = value(&registers, Arg::Reg(x)).add(Affine::constant(1))?;
+                registers.insert(x, incremented);
+            }
+            Instruction::Dec(x) => {
+                let decremented = value(&registers, Arg::Reg(x)).add(Affine::constant(-1))?;
+                registers.insert(x, decremented);
+            }
+            Instruction::Add(x, y) => {
+                let sum = value(&registers, Arg::Reg(y)).add(value(&registers, Arg::Reg(x)))?;
+                registers.insert(y, sum);
+            }
+            Instruction::Mul(x, y) => {
+                let product = value(&registers, Arg::Reg(y)).mul(value(&registers, Arg::Reg(x)))?;
+                registers.insert(y, product);
+            }
+            Instruction::Jnz(x, y) => {
+                if value(&registers, x).as_constant()? != 0 {
+                    let offset = value(&registers, y).as_constant()?;
+                    next = pc.checked_add(offset as isize)?;
+                }
+            }
+            Instruction::Tgl(x) => {
+                let offset = value(&registers, Arg::Reg(x)).as_constant()?;
+                if let Some(i) = pc.checked_add(offset as isize) {
+                    if i >= 0 {
+                        program.toggle(i as usize);
+                    }
+                }
+            }
+            // Anything sent before `until` would need to be checked too.
+            Instruction::Out(_) => return None,
+            Instruction::Nop
+            | Instruction::CpyInvalid(_, _)
+            | Instruction::IncInvalid(_)
+            | Instruction::DecInvalid(_) => {}
+        }
+        pc = next;
+    }
+    None
+}
+
+/// Find the start of a loop that is always taken and contains `out`.
+fn find_output_loop(program: &Program) -> Option<isize> {
+    let instructions = &program.optimized;
+    instructions.iter()
+        .enumerate()
+        .filter_map(|(i, instruction)| match *instruction {
+            Instruction::Jnz(Arg::Val(x), Arg::Val(y)) if x != 0 && y < 0 => {
+                Some((i, i as isize + y as isize))
+            }
+            _ => None,
+        })
+        .find(|&(i, start)| {
+            start >= 0 && instructions[start as usize..i].iter()
+                .any(|instruction| matches!(*instruction, Instruction::Out(_)))
+        })
+        .map(|(_, start)| start)
+}
+
+/// Check that running the loop at `start` sends the bits of `n`, least
+/// significant first, and then does so again.
+fn sends_bits(program: &Program, start: isize, registers: HashMap<Reg, i64>, n: i64) -> bool {
+    let mut bits = Vec::new();
+    let mut remaining = n;
+    while remaining > 0 {
+        bits.push(remaining % 2);
+        remaining /= 2;
+    }
+    let want = bits.iter().chain(bits.iter()).cloned().collect::<Vec<_>>();
+
+    let mut vm = Vm::new(program.clone(), registers, Default::default());
+    vm.pc = start;
+    for _ in 0..MAX_STEPS {
+        if vm.output.len() == want.len() {
+            return vm.output == want;
+        }
+        if vm.step().is_err() {
+            return false;
+        }
+    }
+    false
+}
+
+/// Find the smallest positive value of `input` for which the program sends a
+/// clock signal of 0, 1, 0, 1, ... forever, without simulating every
+/// candidate.
+///
+/// This only works for programs that compute an affine function `n` of the
+/// input and then repeatedly send the bits of `n`. The first part is proven by
+/// running the program symbolically up to the output loop. The output loop
+/// itself isn't affine (it divides by two), so the check that it sends the bits
+/// of `n` is only empirical: it is run concretely for a few sample inputs, and
+/// then for the answer before returning it.
+///
+/// Returns `None` if the program doesn't have this structure, or if the
+/// answer doesn't pass that final check.
+pub fn min_clock_input(program: &Program, input: Reg) -> Option<i64> {
+    let start = find_output_loop(program)?;
+    let symbolic = run_until(program, input, start)?;
+
+    let check = |n: Affine, x: i64| {
+        let registers = symbolic.iter()
+            .map(|(&r, v)| v.eval(x).map(|v| (r, v)))
+            .collect::<Option<HashMap<_, _>>>();
+        match (registers, n.eval(x)) {
+            (Some(registers), Some(n)) if n > 0 => sends_bits(program, start, registers, n),
+            _ => false,
+        }
+    };
+
+    let samples = (1..=16).chain(vec![100, 1000, 10_000]).collect::<Vec<i64>>();
+    let sent = symbolic.values()
+        .cloned()
+        .filter(|n| n.coeff > 0)
+        .find(|&n| samples.iter().all(|&x| check(n, x)))?;
+
+    // Sending the bits of n gives 0, 1, 0, 1, ... when n is 0b10, 0b1010,
+    // 0b101010, etc. It then continues seamlessly as n is sent again.
+    let mut n: i64 = 2;
+    let a = loop {
+        let offset = n - sent.constant;
+        if offset > 0 && offset % sent.coeff == 0 {
+            break offset / sent.coeff;
+        }
+        n = n.checked_mul(4)?.checked_add(2)?;
+    };
+
+    if check(sent, a) {
+        Some(a)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::assembunny::find_min_input;
+    use crate::assembunny::tests::parse;
+
+    #[test]
+    fn test_affine() {
+        let a = Affine { coeff: 2, constant: 3 };
+        assert_eq!(a.add(Affine::constant(4)), Some(Affine { coeff: 2, constant: 7 }));
+        assert_eq!(a.mul(Affine::constant(3)), Some(Affine { coeff: 6, constant: 9 }));
+        assert_eq!(a.mul(Affine::unknown()), None);
+        assert_eq!(a.eval(5), Some(13));
+    }
+
+    #[test]
+    fn test_min_clock_input() {
+        // Sends the bits of 3a + 6 forever.
+        let program = Program::new(parse("\
+            cpy a b\n\
+            cpy 3 c\n\
+            cpy b e\n\
+            inc d\n\
+            dec e\n\
+            jnz e -2\n\
+            dec c\n\
+            jnz c -5\n\
+            inc d\n\
+            inc d\n\
+            inc d\n\
+            inc d\n\
+            inc d\n\
+            inc d\n\
+            cpy d a\n\
+            cpy a b\n\
+            cpy 0 a\n\
+            cpy 2 c\n\
+            jnz b 2\n\
+            jnz 1 6\n\
+            dec b\n\
+            dec c\n\
+            jnz c -4\n\
+            inc a\n\
+            jnz 1 -7\n\
+            cpy 2 b\n\
+            jnz c 2\n\
+            jnz 1 4\n\
+            dec b\n\
+            dec c\n\
+            jnz 1 -4\n\
+            out b\n\
+            jnz a -17\n\
+            jnz 1 -19"));
+
+        // 3a + 6 = 42
+        assert_eq!(min_clock_input(&program, 'a'), Some(12));
+
+        let a = find_min_input(&program, 'a', Default::default(), |vm: &mut Vm<i64>| {
+            (0..20).all(|i| vm.next_output(1000) == Ok(i % 2))
+        });
+        assert_eq!(a, Some(12));
+    }
+
+    #[test]
+    fn test_min_clock_input_unsampled_loop() {
+        // Sends the bits of a + 25 forever, except that each time round it
+        // also sends a 1 first when a + 25 is 42. None of the samples get
+        // there, so only checking the answer itself catches it.
+        let program = Program::new(parse("\
+            cpy a d\n\
+            cpy 25 e\n\
+            inc d\n\
+            dec e\n\
+            jnz e -2\n\
+            cpy d c\n\
+            cpy 42 e\n\
+            dec c\n\
+            dec e\n\
+            jnz e -2\n\
+            jnz c 2\n\
+            out 1\n\
+            cpy d a\n\
+            cpy a b\n\
+            cpy 0 a\n\
+            cpy 2 c\n\
+            jnz b 2\n\
+            jnz 1 6\n\
+            dec b\n\
+            dec c\n\
+            jnz c -4\n\
+            inc a\n\
+            jnz 1 -7\n\
+            cpy 2 b\n\
+            jnz c 2\n\
+            jnz 1 4\n\
+            dec b\n\
+            dec c\n\
+            jnz 1 -4\n\
+            out b\n\
+            jnz a -17\n\
+            jnz 1 -26"));
+
+        assert_eq!(min_clock_input(&program, 'a'), None);
+    }
+
+    #[test]
+    fn test_min_clock_input_unknown_branch() {
+        let program = Program::new(parse("jnz a 2\nout 1\nout 0\njnz 1 -1"));
+        assert_eq!(min_clock_input(&program, 'a'), None);
+    }
+}
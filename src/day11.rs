@@ -1,36 +1,29 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use std::fmt;
+use std::iter;
+use std::process;
 
+use clap::{App, Arg};
 use itertools::Itertools;
-use maplit::hashset;
+use nom::alpha;
+
+use super::util::stdin_as_string;
 
-#[allow(dead_code)]
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-enum Fuel {
-    Promethium,
-    Cobalt,
-    Curium,
-    Ruthenium,
-    Plutonium,
-    Hydrogen,
-    Lithium,
-    Elerium,
-    Dilithium,
-}
+struct Fuel(String);
 
 impl fmt::Display for Fuel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            Fuel::Promethium => write!(f, "Pr"),
-            Fuel::Cobalt => write!(f, "Co"),
-            Fuel::Curium => write!(f, "Cu"),
-            Fuel::Ruthenium => write!(f, "R"),
-            Fuel::Plutonium => write!(f, "Pl"),
-            Fuel::Hydrogen => write!(f, "H"),
-            Fuel::Lithium => write!(f, "L"),
-            Fuel::Elerium => write!(f, "E"),
-            Fuel::Dilithium => write!(f, "D"),
+        // Abbreviate to the first two letters, e.g. Pr for promethium.
+        let mut chars = self.0.chars();
+        if let Some(c) = chars.next() {
+            write!(f, "{}", c.to_ascii_uppercase())?;
         }
+        if let Some(c) = chars.next() {
+            write!(f, "{}", c)?;
+        }
+        Ok(())
     }
 }
 
@@ -40,6 +33,55 @@ enum Item {
     Microchip(Fuel),
 }
 
+named!(item<&str, Item>,
+    do_parse!(
+        tag!("a ") >>
+        fuel: alpha >>
+        kind: alt!(tag!(" generator") | tag!("-compatible microchip")) >>
+        (
+            match kind {
+                " generator" => Item::Generator(Fuel(fuel.to_string())),
+                _ => Item::Microchip(Fuel(fuel.to_string())),
+            }
+        )
+    )
+);
+
+named!(floor<&str, HashSet<Item>>,
+    do_parse!(
+        tag!("The ") >>
+        alpha >>
+        tag!(" floor contains ") >>
+        items: alt!(
+            tag!("nothing relevant") => { |_| HashSet::new() } |
+            separated_nonempty_list_complete!(
+                alt!(tag!(", and ") | tag!(", ") | tag!(" and ")),
+                item
+            ) => { |items: Vec<Item>| items.into_iter().collect() }
+        ) >>
+        tag!(".") >>
+        (items)
+    )
+);
+
+/// Parses a floor per line, reporting the line number of the first that's
+/// malformed.
+fn parse_floors(input: &str) -> Result<Vec<HashSet<Item>>, String> {
+    let floors = input.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|&(_, line)| !line.is_empty())
+        .map(|(number, line)| {
+            floor(line).to_result()
+                .map_err(|_| format!("line {}: expected \"The ... floor contains ...\", found {:?}", number, line))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if floors.is_empty() {
+        return Err("no floors".to_string());
+    }
+    Ok(floors)
+}
+
 impl fmt::Display for Item {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
@@ -49,45 +91,10 @@ impl fmt::Display for Item {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
-enum Move {
-    One(Item),
-    Two(Item, Item),
-}
-
 #[derive(Clone, Debug)]
 struct State {
     elevator: usize,
     floors: Vec<HashSet<Item>>,
-    step: i32,
-}
-
-impl State {
-    fn is_valid(&self) -> bool {
-        for floor in &self.floors {
-            let mut microchips: HashSet<&Fuel> = HashSet::new();
-            let mut generators: HashSet<&Fuel> = HashSet::new();
-
-            for item in floor {
-                match *item {
-                    Item::Microchip(ref f) => microchips.insert(f),
-                    Item::Generator(ref f) => generators.insert(f),
-                };
-            }
-
-            let lonely_chips = microchips.difference(&generators)
-                .collect::<HashSet<_>>();
-
-            for microchip in lonely_chips {
-                let mut dangers = generators.clone();
-                dangers.remove(microchip);
-                if !dangers.is_empty() {
-                    return false
-                }
-            }
-        }
-        true
-    }
 }
 
 impl fmt::Display for State {
@@ -112,186 +119,644 @@ impl fmt::Display for State {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
-struct StateKey {
-    elevator: usize,
-    floor_pairs: Vec<VecDeque<usize>>,
+/// Most floors that `Packed::is_valid` can track in its `u64` mask.
+const MAX_FLOORS: usize = 64;
+
+/// Rules for a variant of the puzzle.
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    /// Number of floors, up to `MAX_FLOORS`.
+    floors: usize,
+    /// Maximum number of items the elevator can carry, at least 1.
+    capacity: usize,
+    /// Whether the elevator needs at least one item to move.
+    must_carry: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            floors: 4,
+            capacity: 2,
+            must_carry: true,
+        }
+    }
 }
 
-impl From<State> for StateKey {
-    fn from(state: State) -> Self {
-        // Use a VecDeque to ensure we always have [Microchip, Generator] order using push_front
-        // and push_back
-        let mut seen: HashMap<Fuel, VecDeque<usize>> = HashMap::new();
-
-        for (i, floor) in state.floors.iter().enumerate() {
-            for item in floor {
-                match *item {
-                    Item::Microchip(ref f) => {
-                        let pair = seen.entry(f.clone())
-                            .or_insert_with(|| VecDeque::with_capacity(2));
-                        pair.push_front(i)
-                    },
-                    Item::Generator(ref f) => {
-                        let pair = seen.entry(f.clone())
-                            .or_insert_with(|| VecDeque::with_capacity(2));
-                        pair.push_back(i)
-                    },
+/// Maps a `State` to a `Packed` state. Item `2 * i` is the generator
+/// for `fuels[i]` and item `2 * i + 1` is its microchip.
+struct Layout {
+    fuels: Vec<Fuel>,
+    config: Config,
+    /// Bits used to store each floor number.
+    bits: usize,
+}
+
+impl Layout {
+    fn new(state: &State, config: Config) -> Result<Self, String> {
+        let mut generators = HashSet::new();
+        let mut microchips = HashSet::new();
+        for item in state.floors.iter().flat_map(|floor| floor.iter()) {
+            match *item {
+                Item::Generator(ref f) => generators.insert(f.clone()),
+                Item::Microchip(ref f) => microchips.insert(f.clone()),
+            };
+        }
+        if let Some(fuel) = generators.difference(&microchips).min() {
+            return Err(format!("the {} generator has no microchip", fuel.0));
+        }
+        if let Some(fuel) = microchips.difference(&generators).min() {
+            return Err(format!("the {}-compatible microchip has no generator", fuel.0));
+        }
+        if config.floors == 0 {
+            return Err("there must be at least one floor".to_string());
+        }
+        if config.floors > MAX_FLOORS {
+            return Err(format!("there can be at most {} floors", MAX_FLOORS));
+        }
+        if config.capacity == 0 {
+            return Err("the elevator must be able to carry at least one item".to_string());
+        }
+        assert!(state.floors.len() <= config.floors, "too many floors");
+
+        let bits = (64 - (config.floors as u64 - 1).leading_zeros() as usize).max(1);
+        if (generators.len() * 2 + 1) * bits > 64 {
+            return Err(format!(
+                "{} elements on {} floors are too many to pack into 64 bits",
+                generators.len(), config.floors
+            ));
+        }
+
+        let mut fuels = generators.into_iter().collect::<Vec<_>>();
+        fuels.sort();
+        Ok(Layout { fuels, config, bits })
+    }
+
+    fn num_items(&self) -> usize {
+        self.fuels.len() * 2
+    }
+
+    fn top_floor(&self) -> u64 {
+        self.config.floors as u64 - 1
+    }
+
+    fn floor_mask(&self) -> u64 {
+        (1 << self.bits) - 1
+    }
+
+    fn elevator_shift(&self) -> usize {
+        64 - self.bits
+    }
+
+    fn pack(&self, state: &State) -> Packed {
+        let mut packed = Packed((state.elevator as u64) << self.elevator_shift());
+        for (floor, items) in state.floors.iter().enumerate() {
+            for item in items {
+                let index = match *item {
+                    Item::Generator(ref f) => self.fuels.binary_search(f).unwrap() * 2,
+                    Item::Microchip(ref f) => self.fuels.binary_search(f).unwrap() * 2 + 1,
                 };
+                packed = packed.with_floor(self, index, floor as u64);
             }
         }
+        packed
+    }
 
-        let mut floor_pairs = seen.values().cloned().collect::<Vec<_>>();
-        floor_pairs.sort();
+    fn unpack(&self, packed: Packed) -> State {
+        let mut floors = vec![HashSet::new(); self.config.floors];
+        for (i, fuel) in self.fuels.iter().enumerate() {
+            floors[packed.floor(self, i * 2) as usize].insert(Item::Generator(fuel.clone()));
+            floors[packed.floor(self, i * 2 + 1) as usize].insert(Item::Microchip(fuel.clone()));
+        }
+        State {
+            elevator: packed.elevator(self) as usize,
+            floors,
+        }
+    }
+
+    fn item(&self, index: usize) -> Item {
+        let fuel = self.fuels[index / 2].clone();
+        if index.is_multiple_of(2) { Item::Generator(fuel) } else { Item::Microchip(fuel) }
+    }
+}
+
+/// The floor of every item, using `Layout::bits` bits per item, and the
+/// elevator in the top bits.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct Packed(u64);
+
+impl Packed {
+    fn elevator(self, layout: &Layout) -> u64 {
+        self.0 >> layout.elevator_shift()
+    }
+
+    fn with_elevator(self, layout: &Layout, floor: u64) -> Packed {
+        let shift = layout.elevator_shift();
+        Packed(self.0 & !(layout.floor_mask() << shift) | floor << shift)
+    }
+
+    fn floor(self, layout: &Layout, item: usize) -> u64 {
+        (self.0 >> (item * layout.bits)) & layout.floor_mask()
+    }
+
+    fn with_floor(self, layout: &Layout, item: usize, floor: u64) -> Packed {
+        let shift = item * layout.bits;
+        Packed(self.0 & !(layout.floor_mask() << shift) | floor << shift)
+    }
+
+    fn is_valid(self, layout: &Layout) -> bool {
+        let elements = 0..layout.fuels.len();
+
+        // Bit f is set if there's a generator on floor f.
+        let generator_floors = elements.clone()
+            .fold(0u64, |mask, element| mask | 1 << self.floor(layout, element * 2));
+
+        // A microchip is only safe from other generators when it's with its own.
+        elements.into_iter().all(|element| {
+            let generator = self.floor(layout, element * 2);
+            let microchip = self.floor(layout, element * 2 + 1);
+            generator == microchip || generator_floors & 1 << microchip == 0
+        })
+    }
+
+    fn is_goal(self, layout: &Layout) -> bool {
+        (0..layout.num_items()).all(|item| self.floor(layout, item) == layout.top_floor())
+    }
+
+    /// Elements are interchangeable, so states that only differ by which
+    /// element is where share a key: the elevator and the sorted
+    /// (generator floor, microchip floor) pairs.
+    fn key(self, layout: &Layout) -> Packed {
+        let pair_bits = layout.bits * 2;
+        let pair_mask = (1 << pair_bits) - 1;
+        let mut pairs = (0..layout.fuels.len())
+            .map(|element| (self.0 >> (element * pair_bits)) & pair_mask)
+            .collect::<Vec<_>>();
+        pairs.sort();
+        let packed = pairs.iter()
+            .enumerate()
+            .fold(0, |packed, (element, pair)| packed | pair << (element * pair_bits));
+        Packed(packed).with_elevator(layout, self.elevator(layout))
+    }
 
-        StateKey {
-            elevator: state.elevator,
-            floor_pairs,
+    /// Lower bound on the moves left: each move carries at most `capacity`
+    /// items up one floor.
+    fn heuristic(self, layout: &Layout) -> i32 {
+        let distance = (0..layout.num_items())
+            .map(|item| (layout.top_floor() - self.floor(layout, item)) as i32)
+            .sum::<i32>();
+        let capacity = layout.config.capacity as i32;
+        (distance + capacity - 1) / capacity
+    }
+
+    fn next_states(self, layout: &Layout) -> Vec<Packed> {
+        let elevator = self.elevator(layout);
+        let here = (0..layout.num_items())
+            .filter(|&item| self.floor(layout, item) == elevator)
+            .collect::<Vec<_>>();
+
+        // Each move is a list of the items to carry.
+        let min_items = if layout.config.must_carry { 1 } else { 0 };
+        let moves = (min_items..=layout.config.capacity)
+            .flat_map(|n| here.iter().cloned().combinations(n))
+            .collect::<Vec<_>>();
+
+        let mut directions = Vec::with_capacity(2);
+        if elevator > 0 {
+            directions.push(elevator - 1);
         }
+        if elevator < layout.top_floor() {
+            directions.push(elevator + 1);
+        }
+
+        let mut states = Vec::new();
+        for next_floor in directions {
+            for move_ in &moves {
+                let new_state = move_.iter()
+                    .fold(self.with_elevator(layout, next_floor), |state, &item| {
+                        state.with_floor(layout, item, next_floor)
+                    });
+                if new_state.is_valid(layout) {
+                    states.push(new_state);
+                }
+            }
+        }
+        states
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Algorithm {
+    Bfs,
+    AStar,
+}
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Direction {
+    Up,
+    Down,
+}
+
+#[derive(Debug)]
+struct Move {
+    direction: Direction,
+    items: Vec<Item>,
+    /// The state after the move.
+    state: State,
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.direction)?;
+        for item in &self.items {
+            write!(f, " {}", item)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Solution {
+    steps: i32,
+    /// Number of states taken off the queue.
+    expanded: usize,
+    moves: Vec<Move>,
+}
 
-fn find_min_moves(initial: State) -> Option<i32> {
-    let mut visited: HashSet<StateKey> = HashSet::new();
+/// Path from the initial state to the goal, inclusive.
+struct Search {
+    path: Vec<Packed>,
+    expanded: usize,
+}
+
+/// Returns `Ok(None)` if everything can't be moved to the top floor, or an
+/// error if `initial` can't be solved with this representation at all.
+fn find_min_moves(initial: &State, config: Config, algorithm: Algorithm) -> Result<Option<Solution>, String> {
+    let layout = Layout::new(initial, config)?;
+    let initial = layout.pack(initial);
+    let search = match algorithm {
+        Algorithm::Bfs => bfs(initial, &layout),
+        Algorithm::AStar => a_star(initial, &layout),
+    };
+    let search = match search {
+        Some(search) => search,
+        None => return Ok(None),
+    };
+
+    let moves = search.path.windows(2)
+        .map(|pair| {
+            let (from, to) = (pair[0], pair[1]);
+            let direction = if to.elevator(&layout) > from.elevator(&layout) {
+                Direction::Up
+            } else {
+                Direction::Down
+            };
+            let mut items = (0..layout.num_items())
+                .filter(|&item| from.floor(&layout, item) != to.floor(&layout, item))
+                .map(|item| layout.item(item))
+                .collect::<Vec<_>>();
+            items.sort();
+            Move { direction, items, state: layout.unpack(to) }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Some(Solution {
+        steps: moves.len() as i32,
+        expanded: search.expanded,
+        moves,
+    }))
+}
+
+/// Follows parent pointers back from `goal` to `initial`.
+fn reconstruct_path(came_from: &HashMap<Packed, Packed>, initial: Packed, goal: Packed) -> Vec<Packed> {
+    let mut path = vec![goal];
+    let mut state = goal;
+    while state != initial {
+        state = came_from[&state];
+        path.push(state);
+    }
+    path.reverse();
+    path
+}
+
+fn bfs(initial: Packed, layout: &Layout) -> Option<Search> {
+    let mut visited: HashSet<Packed> = HashSet::new();
+    // Parent of each queued state.
+    let mut came_from: HashMap<Packed, Packed> = HashMap::new();
     let mut queue = VecDeque::new();
-    let top_floor = initial.floors.len() - 1;
+    let mut expanded = 0;
+    if initial.is_goal(layout) {
+        return Some(Search { path: vec![initial], expanded });
+    }
+    visited.insert(initial.key(layout));
     queue.push_back(initial);
 
     while let Some(state) = queue.pop_front() {
-        let possible_moves = state.floors[state.elevator].iter()
-            .cloned()
-            .tuple_combinations::<(_, _)>()
-            .map(|(a, b)| Move::Two(a, b))
-            .chain(state.floors[state.elevator].iter().cloned().map(Move::One))
-            .collect::<Vec<_>>();
+        expanded += 1;
+        for new_state in state.next_states(layout) {
+            if new_state.is_goal(layout) {
+                came_from.insert(new_state, state);
+                let path = reconstruct_path(&came_from, initial, new_state);
+                return Some(Search { path, expanded });
+            }
 
-        let directions: Vec<i32> = match state.elevator {
-            0 => vec![1],
-            n if n == top_floor => vec![-1],
-            _ => vec![-1, 1],
-        };
+            if visited.insert(new_state.key(layout)) {
+                came_from.insert(new_state, state);
+                queue.push_back(new_state)
+            }
+        }
+    }
+    None
+}
 
-        for direction in directions {
-            for move_ in possible_moves.clone() {
-                let cur_floor = state.elevator;
-                let next_floor = ((cur_floor as i32) + direction) as usize;
-
-                let mut new_state = state.clone();
-                new_state.elevator = next_floor;
-                new_state.step += 1;
-
-                match move_ {
-                    Move::One(a) => {
-                        new_state.floors[cur_floor].remove(&a);
-                        new_state.floors[next_floor].insert(a);
-                    },
-                    Move::Two(a, b) => {
-                        new_state.floors[cur_floor].remove(&a);
-                        new_state.floors[cur_floor].remove(&b);
-                        new_state.floors[next_floor].insert(a);
-                        new_state.floors[next_floor].insert(b);
-                    }
-                }
+fn a_star(initial: Packed, layout: &Layout) -> Option<Search> {
+    // Ties are broken in favour of states further from the start.
+    let mut frontier = BinaryHeap::new();
+    let mut cost_so_far: HashMap<Packed, i32> = HashMap::new();
+    // Parent of each queued state, keyed by the state itself rather than its
+    // key so the reconstructed path is made of real moves.
+    let mut came_from: HashMap<Packed, Packed> = HashMap::new();
+    let mut expanded = 0;
+    frontier.push(Reverse((initial.heuristic(layout), 0, initial)));
+    cost_so_far.insert(initial.key(layout), 0);
+
+    while let Some(Reverse((_, neg_step, state))) = frontier.pop() {
+        let step = -neg_step;
+        if cost_so_far[&state.key(layout)] < step {
+            // A shorter route to this state was found after it was queued.
+            continue;
+        }
+        expanded += 1;
 
-                if new_state.is_valid() {
-                    if new_state.floors[0].is_empty() && new_state.floors[1].is_empty() && new_state.floors[2].is_empty() {
-                        return Some(new_state.step)
-                    }
+        if state.is_goal(layout) {
+            let path = reconstruct_path(&came_from, initial, state);
+            return Some(Search { path, expanded });
+        }
 
-                    if visited.insert(new_state.clone().into()) {
-                        queue.push_back(new_state)
-                    }
-                }
+        for new_state in state.next_states(layout) {
+            let key = new_state.key(layout);
+            let new_step = step + 1;
+            if cost_so_far.get(&key).is_none_or(|&cost| new_step < cost) {
+                cost_so_far.insert(key, new_step);
+                came_from.insert(new_state, state);
+                let priority = new_step + new_state.heuristic(layout);
+                frontier.push(Reverse((priority, -new_step, new_state)));
             }
         }
     }
     None
 }
 
-pub(crate) fn solve() {
+pub(crate) fn solve(args: &[&str]) {
+    let matches = App::new("aoc 11")
+        .arg(Arg::with_name("compare")
+            .long("compare")
+            .help("Compare the states expanded by BFS and A*"))
+        .arg(Arg::with_name("floors")
+            .long("floors")
+            .takes_value(true)
+            .validator(|s| match s.parse::<usize>() {
+                Ok(n) if (1..=MAX_FLOORS).contains(&n) => Ok(()),
+                _ => Err(format!("must be a whole number from 1 to {}", MAX_FLOORS)),
+            })
+            .help("Number of floors, adding empty ones above the input's"))
+        .arg(Arg::with_name("capacity")
+            .long("capacity")
+            .takes_value(true)
+            .validator(|s| match s.parse::<usize>() {
+                Ok(n) if n >= 1 => Ok(()),
+                _ => Err("must be a whole number of at least 1".to_string()),
+            })
+            .help("Number of items the elevator can carry [default: 2]"))
+        .arg(Arg::with_name("allow-empty")
+            .long("allow-empty")
+            .help("Let the elevator move without carrying anything"))
+        .arg(Arg::with_name("show")
+            .long("show")
+            .help("Print the state after every move"))
+        .get_matches_from(iter::once("aoc 11").chain(args.iter().cloned()));
+
+    let floors = parse_floors(&stdin_as_string()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let usize_arg = |name| {
+        matches.value_of(name)
+            .map(|_| clap::value_t!(matches.value_of(name), usize).unwrap_or_else(|e| e.exit()))
+    };
+    let config = Config {
+        floors: usize_arg("floors").unwrap_or(0).max(floors.len()),
+        capacity: usize_arg("capacity").unwrap_or(Config::default().capacity),
+        must_carry: !matches.is_present("allow-empty"),
+    };
+
     let state1 = State {
         elevator: 0,
-        floors: vec![
-            hashset! {
-                Item::Generator(Fuel::Promethium),
-                Item::Microchip(Fuel::Promethium),
-            },
-            hashset! {
-                Item::Generator(Fuel::Cobalt),
-                Item::Generator(Fuel::Curium),
-                Item::Generator(Fuel::Ruthenium),
-                Item::Generator(Fuel::Plutonium),
-            },
-            hashset! {
-                Item::Microchip(Fuel::Cobalt),
-                Item::Microchip(Fuel::Curium),
-                Item::Microchip(Fuel::Ruthenium),
-                Item::Microchip(Fuel::Plutonium),
-            },
-            hashset! {},
-        ],
-        step: 0,
+        floors,
     };
 
-    let state2 = State {
-        elevator: 0,
-        floors: vec![
+    let mut state2 = state1.clone();
+    for name in &["elerium", "dilithium"] {
+        state2.floors[0].insert(Item::Generator(Fuel(name.to_string())));
+        state2.floors[0].insert(Item::Microchip(Fuel(name.to_string())));
+    }
+
+    for (part, state) in vec![state1, state2].into_iter().enumerate() {
+        let part = part + 1;
+        let find = |algorithm| {
+            match find_min_moves(&state, config, algorithm) {
+                Ok(Some(solution)) => solution,
+                Ok(None) => {
+                    eprintln!("Part {}: everything can't be moved to the top floor", part);
+                    process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Part {}: {}", part, e);
+                    process::exit(1);
+                }
+            }
+        };
+        if matches.is_present("compare") {
+            for &algorithm in &[Algorithm::Bfs, Algorithm::AStar] {
+                let solution = find(algorithm);
+                println!(
+                    "Part {} ({:?}): {} ({} states expanded)",
+                    part, algorithm, solution.steps, solution.expanded
+                );
+            }
+        } else {
+            let solution = find(Algorithm::AStar);
+            println!("Part {}: {}", part, solution.steps);
+            if matches.is_present("show") {
+                println!("{:>3}  {:<16} {}", "", "", state);
+                for (i, move_) in solution.moves.iter().enumerate() {
+                    println!("{:>3}. {:<16} {}", i + 1, move_.to_string(), move_.state);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use maplit::hashset;
+
+    const EXAMPLE: &str = "\
+        The first floor contains a hydrogen-compatible microchip and a lithium-compatible microchip.
+        The second floor contains a hydrogen generator.
+        The third floor contains a lithium generator.
+        The fourth floor contains nothing relevant.";
+
+    #[test]
+    fn test_parse() {
+        let hydrogen = Fuel("hydrogen".to_string());
+        let lithium = Fuel("lithium".to_string());
+        assert_eq!(parse_floors(EXAMPLE).unwrap(), vec![
             hashset! {
-                Item::Generator(Fuel::Promethium),
-                Item::Microchip(Fuel::Promethium),
-                Item::Generator(Fuel::Elerium),
-                Item::Microchip(Fuel::Elerium),
-                Item::Generator(Fuel::Dilithium),
-                Item::Microchip(Fuel::Dilithium),
+                Item::Microchip(hydrogen.clone()),
+                Item::Microchip(lithium.clone()),
             },
             hashset! {
-                Item::Generator(Fuel::Cobalt),
-                Item::Generator(Fuel::Curium),
-                Item::Generator(Fuel::Ruthenium),
-                Item::Generator(Fuel::Plutonium),
+                Item::Generator(hydrogen),
             },
             hashset! {
-                Item::Microchip(Fuel::Cobalt),
-                Item::Microchip(Fuel::Curium),
-                Item::Microchip(Fuel::Ruthenium),
-                Item::Microchip(Fuel::Plutonium),
+                Item::Generator(lithium),
             },
             hashset! {},
-        ],
-        step: 0,
-    };
+        ]);
+
+        let floors = parse_floors("\
+            The first floor contains a thulium generator, a thulium-compatible microchip, \
+            a plutonium generator, and a strontium generator.").unwrap();
+        assert_eq!(floors[0].len(), 4);
+
+        assert_eq!(
+            parse_floors("The first floor contains nothing relevant.\nThe second floor has a lift."),
+            Err("line 2: expected \"The ... floor contains ...\", found \"The second floor has a lift.\"".to_string())
+        );
+        assert_eq!(parse_floors(""), Err("no floors".to_string()));
+        assert_eq!(parse_floors("\n  \n"), Err("no floors".to_string()));
+    }
 
-    println!("Part 1: {}", find_min_moves(state1).unwrap());
-    println!("Part 2: {}", find_min_moves(state2).unwrap());
-}
+    #[test]
+    fn example() {
+        let state = State {
+            elevator: 0,
+            floors: parse_floors(EXAMPLE).unwrap(),
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let bfs = find_min_moves(&state, Config::default(), Algorithm::Bfs).unwrap().unwrap();
+        let a_star = find_min_moves(&state, Config::default(), Algorithm::AStar).unwrap().unwrap();
+        assert_eq!(bfs.steps, 11);
+        assert_eq!(a_star.steps, 11);
+        assert!(a_star.expanded < bfs.expanded);
+    }
 
     #[test]
-    fn example() {
+    fn test_moves() {
         let state = State {
             elevator: 0,
-            floors: vec![
-                hashset! {
-                Item::Microchip(Fuel::Hydrogen),
-                Item::Microchip(Fuel::Lithium),
-            },
-                hashset! {
-                Item::Generator(Fuel::Hydrogen),
-            },
-                hashset! {
-                Item::Generator(Fuel::Lithium),
-            },
-                hashset! {},
-            ],
-            step: 0,
+            floors: parse_floors(EXAMPLE).unwrap(),
         };
+        let layout = Layout::new(&state, Config::default()).unwrap();
+
+        for &algorithm in &[Algorithm::Bfs, Algorithm::AStar] {
+            let solution = find_min_moves(&state, Config::default(), algorithm).unwrap().unwrap();
+            assert_eq!(solution.moves.len(), 11);
+
+            let mut previous = layout.pack(&state);
+            for move_ in &solution.moves {
+                let next = layout.pack(&move_.state);
+                assert!(previous.next_states(&layout).contains(&next), "{}", move_);
+                assert!(move_.items.len() == 1 || move_.items.len() == 2);
+                previous = next;
+            }
+            assert!(previous.is_goal(&layout));
+        }
+
+        // The only first move is taking the hydrogen microchip up to its generator.
+        let solution = find_min_moves(&state, Config::default(), Algorithm::Bfs).unwrap().unwrap();
+        let first = &solution.moves[0];
+        assert_eq!(first.direction, Direction::Up);
+        assert_eq!(first.items, vec![Item::Microchip(Fuel("hydrogen".to_string()))]);
+        assert_eq!(first.state.to_string(), "E2: [LiM] [HyG HyM] [LiG] []");
+    }
+
+    #[test]
+    fn test_packed() {
+        let state = State {
+            elevator: 1,
+            floors: parse_floors(EXAMPLE).unwrap(),
+        };
+        let layout = Layout::new(&state, Config::default()).unwrap();
+        let packed = layout.pack(&state);
+        assert_eq!(packed.elevator(&layout), 1);
+        assert!(packed.is_valid(&layout));
+        // Hydrogen generator and microchip, then lithium.
+        let floors = (0..4).map(|i| packed.floor(&layout, i)).collect::<Vec<_>>();
+        assert_eq!(floors, vec![1, 0, 2, 0]);
+
+        // Swapping hydrogen and lithium gives an equivalent state.
+        let swapped = Packed(packed.0 & !0xff | (packed.0 & 0xf) << 4 | (packed.0 & 0xf0) >> 4);
+        assert_ne!(swapped, packed);
+        assert_eq!(swapped.key(&layout), packed.key(&layout));
+    }
 
-        let num =  find_min_moves(state).unwrap();
-        assert_eq!(num, 11);
+    #[test]
+    fn test_variants() {
+        let state = State {
+            elevator: 0,
+            floors: parse_floors(EXAMPLE).unwrap(),
+        };
+        let variants = vec![
+            (Config { floors: 6, ..Default::default() }, Some(21)),
+            (Config { capacity: 3, ..Default::default() }, Some(9)),
+            (Config { capacity: 1, ..Default::default() }, None),
+            (Config { must_carry: false, ..Default::default() }, Some(9)),
+        ];
+
+        for (config, steps) in variants {
+            let bfs = find_min_moves(&state, config, Algorithm::Bfs);
+            let a_star = find_min_moves(&state, config, Algorithm::AStar);
+            assert_eq!(bfs.unwrap().map(|s| s.steps), steps, "{:?}", config);
+            assert_eq!(a_star.unwrap().map(|s| s.steps), steps, "{:?}", config);
+        }
+
+        let too_many_floors = Config { floors: MAX_FLOORS + 1, ..Default::default() };
+        assert!(find_min_moves(&state, too_many_floors, Algorithm::AStar).is_err());
+        let no_capacity = Config { capacity: 0, ..Default::default() };
+        for &algorithm in &[Algorithm::Bfs, Algorithm::AStar] {
+            assert!(find_min_moves(&state, no_capacity, algorithm).is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_layout() {
+        let unpaired = State {
+            elevator: 0,
+            floors: parse_floors("\
+                The first floor contains a hydrogen generator and a hydrogen-compatible microchip.
+                The second floor contains a lithium generator.").unwrap(),
+        };
+        assert_eq!(
+            find_min_moves(&unpaired, Config::default(), Algorithm::AStar).err(),
+            Some("the lithium generator has no microchip".to_string())
+        );
+
+        // 32 elements need 65 items' worth of 2-bit floor numbers.
+        let items = (0..32)
+            .flat_map(|i| {
+                let fuel = Fuel(format!("fuel{}", i));
+                vec![Item::Generator(fuel.clone()), Item::Microchip(fuel)]
+            })
+            .collect();
+        let crowded = State {
+            elevator: 0,
+            floors: vec![items],
+        };
+        assert!(find_min_moves(&crowded, Config::default(), Algorithm::AStar).is_err());
     }
 }
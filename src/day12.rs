@@ -12,12 +12,12 @@ pub(crate) fn solve() {
         .map(|l| assembunny::instruction(&l).to_result().unwrap())
         .collect::<Vec<_>>();
 
-    let mut registers1 = HashMap::new();
+    let mut registers1: HashMap<char, i32> = HashMap::new();
     let mut registers2 = hashmap!{'c' => 1};
 
-    assembunny::run(instructions.clone(), &mut registers1, None);
+    assembunny::run(instructions.clone(), &mut registers1, Default::default());
     println!("Part 1: {}", &registers1[&'a']);
 
-    assembunny::run(instructions, &mut registers2, None);
+    assembunny::run(instructions, &mut registers2, Default::default());
     println!("Part 2: {}", &registers2[&'a']);
 }
@@ -1,96 +1,304 @@
-use std::collections::{HashSet, VecDeque};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::iter;
+use std::process;
 
-use super::util::stdin_as_string;
-
-type Point = (i32, i32);
+use clap::{App, Arg};
+use itertools::Itertools;
+use termion::color;
 
+use super::grid::{self, Graph, NotFound, Pos};
+use super::util::stdin_as_string;
 
 struct Grid {
     fav: i32,
+    walls: RefCell<WallCache>,
 }
 
-impl Grid {
-    fn in_bounds(&self, pos: Point) -> bool {
+/// Walls worked out so far, in 64x64 tiles which are filled in the first
+/// time a location in them is asked about.
+#[derive(Debug, Default)]
+struct WallCache {
+    /// A row of bits for each y in a tile, keyed by the tile's top left
+    /// location divided by 64.
+    tiles: HashMap<Pos, [u64; 64]>,
+}
+
+impl WallCache {
+    fn is_wall(&mut self, fav: i32, pos: Pos) -> bool {
         let (x, y) = pos;
-        x >= 0 && y >= 0
+        let rows = self.tiles
+            .entry((x >> 6, y >> 6))
+            .or_insert_with(|| tile(fav, (x & !63, y & !63)));
+        rows[(y & 63) as usize] & 1 << (x & 63) != 0
     }
+}
 
-    fn is_wall(&self, pos: Point) -> bool {
-        let (x, y) = pos;
-        let n = x * x + 3 * x + 2 * x * y + y + y * y + self.fav;
-        n.count_ones() % 2 != 0
+fn tile(fav: i32, top_left: Pos) -> [u64; 64] {
+    let (left, top) = top_left;
+    let mut rows = [0; 64];
+    for (dy, row) in (0..).zip(rows.iter_mut()) {
+        for dx in 0..64 {
+            if is_wall(fav, (left + dx, top + dy)) {
+                *row |= 1 << dx;
+            }
+        }
     }
+    rows
+}
 
-    fn neighbours(&self, pos: Point) -> Vec<Point> {
-        let (x, y) = pos;
-        let neighbours = [
-            (x + 1, y),
-            (x, y - 1),
-            (x - 1, y),
-            (x, y + 1),
-        ];
-
-        neighbours.iter()
+fn is_wall(fav: i32, pos: Pos) -> bool {
+    // Far enough out, the sum no longer fits in an i32.
+    let (x, y) = (i64::from(pos.0), i64::from(pos.1));
+    let n = x * x + 3 * x + 2 * x * y + y + y * y + i64::from(fav);
+    !n.count_ones().is_multiple_of(2)
+}
+
+impl Graph for Grid {
+    type Node = Pos;
+
+    fn neighbours(&self, pos: &Pos) -> Vec<Pos> {
+        grid::adjacent(*pos)
+            .iter()
             .cloned()
             .filter(|p| self.in_bounds(*p))
             .filter(|p| !self.is_wall(*p))
-            .collect::<Vec<Point>>()
-    }
-
-    #[allow(dead_code)]
-    fn draw(&self, to: Point) {
-        let (width, height) = to;
-        for y in 0..height {
-            for x in 0..width {
-                if self.is_wall((x, y)) {
-                    print!("#")
-                } else {
-                    print!(".")
-                }
-            }
-            println!();
+            .collect::<Vec<Pos>>()
+    }
+}
+
+impl Grid {
+    fn new(fav: i32) -> Self {
+        Grid {
+            fav,
+            walls: RefCell::new(WallCache::default()),
         }
     }
+
+    fn in_bounds(&self, pos: Pos) -> bool {
+        let (x, y) = pos;
+        x >= 0 && y >= 0
+    }
+
+    fn is_wall(&self, pos: Pos) -> bool {
+        self.walls.borrow_mut().is_wall(self.fav, pos)
+    }
+
+    /// Every location from (0, 0) up to but excluding `size`, by row.
+    fn cells(&self, size: Pos, path: &[Pos], reachable: &HashSet<Pos>) -> Vec<Vec<Cell>> {
+        let path = path.iter().collect::<HashSet<_>>();
+        let (width, height) = size;
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| match (x, y) {
+                        pos if path.contains(&pos) => Cell::Path,
+                        pos if reachable.contains(&pos) => Cell::Reachable,
+                        pos if self.is_wall(pos) => Cell::Wall,
+                        _ => Cell::Open,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
 }
 
-fn find_goal(grid: &Grid, start: Point, goal: Point) -> (i32, i32) {
-    let mut visited: HashSet<Point> = HashSet::new();
-    let mut new = VecDeque::new();
-    visited.insert(start);
-    new.push_back(start);
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Cell {
+    Wall,
+    Open,
+    /// Within the step budget of the start.
+    Reachable,
+    /// On the shortest path to the goal.
+    Path,
+}
 
-    let mut steps = 0;
-    let mut part1: Option<i32> = None;
-    let mut part2: Option<i32> = None;
+impl Cell {
+    fn symbol(self) -> char {
+        match self {
+            Cell::Wall => '#',
+            Cell::Open => '.',
+            Cell::Reachable => '+',
+            Cell::Path => 'O',
+        }
+    }
 
-    while part1.is_none() || part2.is_none() {
-        let mut open = new.clone();
-        new = VecDeque::new();
-        while let Some(current) = open.pop_front() {
-            if current == goal {
-                part1 = Some(steps)
-            }
-            for neighbour in grid.neighbours(current) {
-                if visited.insert(neighbour) {
-                    new.push_back(neighbour);
-                }
-            }
+    fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Cell::Wall => (64, 64, 64),
+            Cell::Open => (255, 255, 255),
+            Cell::Reachable => (170, 200, 255),
+            Cell::Path => (220, 40, 40),
         }
-        steps += 1;
-        if steps == 50 {
-            part2 = Some(visited.len() as i32)
+    }
+}
+
+/// Draws the maze as text, with the path and reachable locations coloured
+/// in as well if `colour`.
+fn render_text(cells: &[Vec<Cell>], colour: bool) -> String {
+    cells.iter()
+        .map(|row| {
+            row.iter()
+                .map(|&cell| match cell {
+                    Cell::Path if colour => {
+                        format!("{}{}{}", color::Fg(color::Red), cell.symbol(), color::Fg(color::Reset))
+                    }
+                    Cell::Reachable if colour => {
+                        format!("{}{}{}", color::Bg(color::Blue), cell.symbol(), color::Bg(color::Reset))
+                    }
+                    _ => cell.symbol().to_string(),
+                })
+                .collect::<String>()
+        })
+        .join("\n")
+}
+
+/// Draws the maze as a plain PPM image with a pixel per location.
+fn render_ppm(cells: &[Vec<Cell>]) -> String {
+    let height = cells.len();
+    let width = cells.first().map_or(0, |row| row.len());
+    let mut ppm = format!("P3\n{} {}\n255\n", width, height);
+    for row in cells {
+        let pixels = row.iter()
+            .map(|cell| {
+                let (r, g, b) = cell.rgb();
+                format!("{} {} {}", r, g, b)
+            })
+            .join(" ");
+        ppm.push_str(&pixels);
+        ppm.push('\n');
+    }
+    ppm
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Algorithm {
+    Bfs,
+    AStar,
+}
+
+/// Shortest path from `start` to `goal`, or why there isn't one within
+/// `max_steps`. The maze is infinite, so without a limit an unreachable goal
+/// would be searched for forever unless the start is walled in.
+fn find_goal(
+    grid: &Grid,
+    start: Pos,
+    goal: Pos,
+    max_steps: usize,
+    algorithm: Algorithm,
+) -> Result<grid::Path<Pos>, NotFound> {
+    if !grid.in_bounds(goal) || grid.is_wall(goal) {
+        return Err(NotFound::Unreachable);
+    }
+    let is_goal = |&pos: &Pos| pos == goal;
+    match algorithm {
+        Algorithm::Bfs => grid::bfs(grid, start, is_goal, Some(max_steps)),
+        Algorithm::AStar => {
+            let heuristic = |&pos: &Pos| grid::manhattan(pos, goal);
+            grid::a_star(grid, start, is_goal, heuristic, Some(max_steps))
         }
     }
-    (part1.unwrap(), part2.unwrap())
 }
 
-pub(crate) fn solve() {
+/// Locations, including `start`, at most `steps` away.
+fn reachable(grid: &Grid, start: Pos, steps: usize) -> HashSet<Pos> {
+    grid::reachable(grid, start, Some(steps)).into_keys().collect()
+}
+
+pub(crate) fn solve(args: &[&str]) {
+    let matches = App::new("aoc 13")
+        .arg(Arg::with_name("start")
+            .long("start")
+            .takes_value(true)
+            .default_value("1,1")
+            .validator(|s| grid::parse_pos(&s).map(|_| ()))
+            .help("Location to start from, as X,Y"))
+        .arg(Arg::with_name("goal")
+            .long("goal")
+            .takes_value(true)
+            .default_value("31,39")
+            .validator(|s| grid::parse_pos(&s).map(|_| ()))
+            .help("Location to find the shortest path to, as X,Y"))
+        .arg(Arg::with_name("budget")
+            .long("budget")
+            .takes_value(true)
+            .default_value("50")
+            .help("Steps to count the reachable locations within"))
+        .arg(Arg::with_name("max-steps")
+            .long("max-steps")
+            .takes_value(true)
+            .help("Longest path to search before giving up on the goal \
+                   [default: 4 times the distance to the goal, plus 100]"))
+        .arg(Arg::with_name("a-star")
+            .long("a-star")
+            .help("Search for the goal with A* instead of BFS"))
+        .arg(Arg::with_name("compare")
+            .long("compare")
+            .help("Compare the locations expanded by BFS and A*"))
+        .arg(Arg::with_name("draw")
+            .long("draw")
+            .help("Draw the maze with the path and the reachable locations"))
+        .arg(Arg::with_name("ppm")
+            .long("ppm")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Save the drawing as a PPM image"))
+        .get_matches_from(iter::once("aoc 13").chain(args.iter().cloned()));
+
+    let start = grid::parse_pos(matches.value_of("start").unwrap()).unwrap();
+    let goal = grid::parse_pos(matches.value_of("goal").unwrap()).unwrap();
+    let budget = clap::value_t!(matches.value_of("budget"), usize).unwrap_or_else(|e| e.exit());
+    let max_steps = match matches.value_of("max-steps") {
+        Some(_) => clap::value_t!(matches.value_of("max-steps"), usize).unwrap_or_else(|e| e.exit()),
+        None => 4 * grid::manhattan(start, goal) + 100,
+    };
+
     let fav = stdin_as_string().parse::<i32>().expect("an integer");
-    let grid = Grid { fav };
-//    grid.draw((32, 40));
-    let (part1, part2) = find_goal(&grid, (1, 1), (31, 39));
-    println!("Part 1: {}", part1);
-    println!("Part 2: {}", part2);
+    let grid = Grid::new(fav);
+    if grid.is_wall(start) {
+        eprintln!("{:?} is a wall", start);
+        process::exit(1);
+    }
+
+    if matches.is_present("compare") {
+        for &algorithm in &[Algorithm::Bfs, Algorithm::AStar] {
+            if let Ok(path) = find_goal(&grid, start, goal, max_steps, algorithm) {
+                println!("Part 1 ({:?}): {} ({} locations expanded)", algorithm, path.cost, path.expanded);
+            }
+        }
+    }
+
+    let algorithm = if matches.is_present("a-star") { Algorithm::AStar } else { Algorithm::Bfs };
+    let path = find_goal(&grid, start, goal, max_steps, algorithm);
+    match path {
+        Ok(ref path) => println!("Part 1: {}", path.cost),
+        Err(_) if grid.is_wall(goal) => println!("Part 1: {:?} is a wall", goal),
+        Err(NotFound::Unreachable) => println!("Part 1: {:?} can't be reached from {:?}", goal, start),
+        Err(NotFound::Limit) => println!("Part 1: {:?} can't be reached within {} steps", goal, max_steps),
+    }
+    let reachable = reachable(&grid, start, budget);
+    println!("Part 2: {}", reachable.len());
+
+    if matches.is_present("draw") || matches.is_present("ppm") {
+        // Leave a margin around everything of interest.
+        let size = reachable.iter()
+            .chain(&[start, goal])
+            .fold((0, 0), |(width, height), &(x, y)| (width.max(x + 3), height.max(y + 3)));
+        let path = path.map_or_else(|_| Vec::new(), |path| path.nodes);
+        let cells = grid.cells(size, &path, &reachable);
+
+        if matches.is_present("draw") {
+            println!("{}", render_text(&cells, termion::is_tty(&io::stdout())));
+        }
+        if let Some(file) = matches.value_of("ppm") {
+            fs::write(file, render_ppm(&cells)).unwrap_or_else(|e| {
+                eprintln!("couldn't write {}: {}", file, e);
+                process::exit(1);
+            });
+        }
+    }
 }
 
 #[cfg(test)]
@@ -99,8 +307,71 @@ mod tests {
 
     #[test]
     fn test_example() {
-        let grid = Grid { fav: 10 };
-        let (fewest, _) = find_goal(&grid, (1, 1), (7, 4));
-        assert_eq!(fewest, 11);
+        let grid = Grid::new(10);
+        let path = find_goal(&grid, (1, 1), (7, 4), 100, Algorithm::Bfs).unwrap();
+        assert_eq!(path.cost, 11);
+        assert_eq!(reachable(&grid, (1, 1), 2).len(), 5);
+
+        let a_star = find_goal(&grid, (1, 1), (7, 4), 100, Algorithm::AStar).unwrap();
+        assert_eq!(a_star.cost, 11);
+        assert!(a_star.expanded < path.expanded);
+    }
+
+    #[test]
+    fn test_wall_cache() {
+        let grid = Grid::new(1358);
+        // A far location only fills in the tile around it.
+        assert_eq!(grid.is_wall((60000, 60000)), is_wall(1358, (60000, 60000)));
+        assert_eq!(grid.walls.borrow().tiles.len(), 1);
+        for &pos in &[(300, 2), (0, 0), (70, 500), (1000, 1000), (5, 5), (63, 64)] {
+            assert_eq!(grid.is_wall(pos), is_wall(1358, pos), "{:?}", pos);
+        }
+        for x in 0..100 {
+            for y in 0..100 {
+                assert_eq!(grid.is_wall((x, y)), is_wall(1358, (x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let grid = Grid::new(10);
+        let path = find_goal(&grid, (1, 1), (7, 4), 100, Algorithm::Bfs).unwrap();
+        let reachable = reachable(&grid, (1, 1), 2);
+        let cells = grid.cells((10, 7), &path.nodes, &reachable);
+        assert_eq!(render_text(&cells, false), "\
+            +#.####.##\n\
+            +O#..#...#\n\
+            #OOO.##...\n\
+            ###O#.###.\n\
+            .##OO#.O#.\n\
+            ..##OOOO#.\n\
+            #...##.###");
+
+        let ppm = render_ppm(&cells[..1]);
+        assert!(ppm.starts_with("P3\n10 1\n255\n170 200 255 64 64 64 255 255 255 "));
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let grid = Grid::new(10);
+        // A wall.
+        assert!(find_goal(&grid, (1, 1), (1, 0), 100, Algorithm::Bfs).is_err());
+        assert!(find_goal(&grid, (1, 1), (-1, 0), 100, Algorithm::Bfs).is_err());
+        // Too far for the step limit.
+        for &algorithm in &[Algorithm::Bfs, Algorithm::AStar] {
+            assert_eq!(find_goal(&grid, (1, 1), (7, 4), 10, algorithm).err(), Some(NotFound::Limit));
+            assert!(find_goal(&grid, (1, 1), (7, 4), 11, algorithm).is_ok());
+        }
+
+        // The 468 locations around (1, 1) are walled in and at most 146 steps
+        // away, so (100, 100) is unreachable rather than too far away once
+        // they've all been searched.
+        let grid = Grid::new(1358);
+        assert_eq!(grid::reachable(&grid, (1, 1), None).len(), 468);
+        for &algorithm in &[Algorithm::Bfs, Algorithm::AStar] {
+            assert_eq!(find_goal(&grid, (1, 1), (100, 100), 892, algorithm).err(), Some(NotFound::Unreachable));
+            assert_eq!(find_goal(&grid, (1, 1), (100, 100), 100, algorithm).err(), Some(NotFound::Limit));
+        }
     }
 }
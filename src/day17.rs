@@ -1,15 +1,18 @@
-use std::collections::VecDeque;
-use std::fmt;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{self, Write};
+use std::iter;
+use std::process;
+use std::vec;
+
+use clap::{App, Arg};
 
 use crypto::md5::Md5;
 use crypto::digest::Digest;
-use maplit::hashset;
 
+use super::grid::{self, Graph, Pos};
 use super::util::stdin_as_string;
 
-type Pos = (i32, i32);
-
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 enum Direction {
     Up,
     Down,
@@ -29,98 +32,322 @@ impl fmt::Display for Direction {
     }
 }
 
-fn get_possible_moves(size: Pos, passcode: &str, state: &State) -> Vec<Direction> {
-    let open_chars = hashset!{'b', 'c', 'd', 'e', 'f'};
-
-    let &State { ref path, pos } = state;
-
-    let mut hasher = Md5::new();
-    hasher.input_str(passcode);
-    hasher.input_str(path);
-    let mut output = [0; 16];
-    hasher.result(&mut output);
-    let hash = hasher.result_str();
-
-    hash.chars()
-        .take(4)
-        .enumerate()
-        .filter(|&(_, c)| open_chars.contains(&c))
-        .map(|(i, _)| match i {
-            0 => Direction::Up,
-            1 => Direction::Down,
-            2 => Direction::Left,
-            3 => Direction::Right,
-            _ => unreachable!(),
-        })
-        .filter(|d| match *d {
-            Direction::Up => pos.1 < size.1 - 1,
-            Direction::Down => pos.1 > 0,
-            Direction::Left => pos.0 > 0,
-            Direction::Right => pos.0 < size.0 - 1,
-        })
-        .collect::<Vec<_>>()
-}
-
-#[derive(Debug)]
+impl Direction {
+    fn step(self, pos: Pos) -> Pos {
+        match self {
+            Direction::Up => (pos.0, pos.1 - 1),
+            Direction::Down => (pos.0, pos.1 + 1),
+            Direction::Left => (pos.0 - 1, pos.1),
+            Direction::Right => (pos.0 + 1, pos.1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 struct State {
     path: String,
     pos: Pos,
 }
 
-fn find_paths(passcode: &str) -> Option<(String, usize)> {
-    let size = (4, 4);
+/// A grid of rooms with doors controlled by `passcode`, where y increases
+/// going down.
+struct Vault<'a> {
+    passcode: &'a str,
+    /// The hasher after the passcode, so each path only hashes its moves.
+    prefix: Md5,
+    /// Width and height in rooms.
+    size: Pos,
+    start: Pos,
+    goal: Pos,
+}
 
-    let mut queue = VecDeque::new();
+impl<'a> fmt::Debug for Vault<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vault")
+            .field("passcode", &self.passcode)
+            .field("size", &self.size)
+            .field("start", &self.start)
+            .field("goal", &self.goal)
+            .finish()
+    }
+}
 
-    let state = State {
-        path: "".to_string(),
-        pos: (0, 3)
-    };
+impl<'a> Vault<'a> {
+    /// A vault of `size` rooms, from the top left room to the bottom right.
+    fn new(passcode: &'a str, size: Pos) -> Self {
+        let mut prefix = Md5::new();
+        prefix.input_str(passcode);
+        Vault {
+            passcode,
+            prefix,
+            size,
+            start: (0, 0),
+            goal: (size.0 - 1, size.1 - 1),
+        }
+    }
 
-    queue.push_back(state);
+    fn possible_moves(&self, path: &str, pos: Pos) -> Vec<Direction> {
+        let mut hasher = self.prefix;
+        hasher.input_str(path);
+        self.open_doors(hasher, pos)
+    }
 
-    let goal = (3, 0);
-    let mut shortest_path: Option<String> = None;
-    let mut longest_path: Option<usize> = None;
+    /// The moves out of `pos`, given a hasher that's had the passcode and
+    /// the path so far.
+    fn open_doors(&self, mut hasher: Md5, pos: Pos) -> Vec<Direction> {
+        let mut output = [0; 16];
+        hasher.result(&mut output);
+
+        // The first four hex digits are the doors up, down, left and right,
+        // which are open for b to f.
+        let doors = [output[0] >> 4, output[0] & 0xf, output[1] >> 4, output[1] & 0xf];
+        let (width, height) = self.size;
+        [Direction::Up, Direction::Down, Direction::Left, Direction::Right]
+            .iter()
+            .zip(&doors)
+            .filter(|&(_, &door)| door >= 0xb)
+            .map(|(&direction, _)| direction)
+            .filter(|d| match *d {
+                Direction::Up => pos.1 > 0,
+                Direction::Down => pos.1 < height - 1,
+                Direction::Left => pos.0 > 0,
+                Direction::Right => pos.0 < width - 1,
+            })
+            .collect()
+    }
+}
+
+impl<'a> Graph for Vault<'a> {
+    type Node = State;
+
+    fn neighbours(&self, state: &State) -> Vec<State> {
+        // Reaching the vault ends the path.
+        if state.pos == self.goal {
+            return Vec::new();
+        }
+
+        let State { ref path, pos } = *state;
+        self.possible_moves(path, pos)
+            .into_iter()
+            .map(|move_| {
+                let mut new_path = path.to_owned();
+                new_path.push_str(&format!("{}", move_));
+                State { path: new_path, pos: move_.step(pos) }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Search {
+    /// Breadth first, keeping every partial path in the queue.
+    Bfs,
+    /// Depth first, keeping only the moves left to try at each step of the
+    /// current path.
+    Dfs,
+}
+
+fn find_paths(vault: &Vault<'_>, search: Search) -> Option<(String, usize)> {
+    match search {
+        Search::Bfs => find_paths_bfs(vault),
+        Search::Dfs => find_paths_dfs(vault),
+    }
+}
+
+fn find_paths_bfs(vault: &Vault<'_>) -> Option<(String, usize)> {
+    // Every path is its own state, so no state is seen twice and only the
+    // frontier needs keeping.
+    let mut queue = VecDeque::new();
+    queue.push_back(State {
+        path: "".to_string(),
+        pos: vault.start,
+    });
+
+    let mut shortest_path = None;
+    let mut longest_path = None;
 
     while let Some(state) = queue.pop_front() {
-        let State { ref path, ref pos } = state;
-        let possible_moves = get_possible_moves(size, passcode, &state);
-
-        for move_ in possible_moves {
-            let new_pos = match move_ {
-                Direction::Up => (pos.0, pos.1 + 1),
-                Direction::Down => (pos.0, pos.1 - 1),
-                Direction::Left => (pos.0 - 1, pos.1),
-                Direction::Right => (pos.0 + 1, pos.1),
+        if state.pos == vault.goal {
+            longest_path = Some(state.path.len());
+            if shortest_path.is_none() {
+                shortest_path = Some(state.path);
+            }
+        } else {
+            queue.extend(vault.neighbours(&state));
+        }
+    }
+
+    Some((shortest_path?, longest_path?))
+}
+
+/// Every path from the start to the vault, found depth first. Only the
+/// current path and the moves left to try along it are kept.
+struct Paths<'a> {
+    vault: &'a Vault<'a>,
+    /// Each position on the current path, with the hasher after the path up
+    /// to there and the moves left to try from it.
+    stack: Vec<(Pos, Md5, vec::IntoIter<Direction>)>,
+    path: String,
+    /// The empty path, if the start is the vault and it hasn't been found.
+    empty: Option<String>,
+}
+
+impl<'a> Paths<'a> {
+    fn new(vault: &'a Vault<'a>) -> Self {
+        if vault.start == vault.goal {
+            return Paths {
+                vault,
+                stack: Vec::new(),
+                path: String::new(),
+                empty: Some(String::new()),
             };
+        }
+        let moves = vault.open_doors(vault.prefix, vault.start).into_iter();
+        Paths {
+            vault,
+            stack: vec![(vault.start, vault.prefix, moves)],
+            path: String::new(),
+            empty: None,
+        }
+    }
+}
 
-            let mut new_path = path.to_owned();
-            new_path.push_str(&format!("{}", move_));
+impl<'a> Iterator for Paths<'a> {
+    type Item = String;
 
-            if new_pos == goal {
-                longest_path = Some(new_path.len());
-                if shortest_path.is_none() {
-                    shortest_path = Some(new_path);
+    fn next(&mut self) -> Option<String> {
+        if let Some(empty) = self.empty.take() {
+            return Some(empty);
+        }
+
+        while let Some(&mut (pos, hasher, ref mut moves)) = self.stack.last_mut() {
+            let move_ = match moves.next() {
+                Some(move_) => move_,
+                None => {
+                    self.stack.pop();
+                    self.path.pop();
+                    continue;
                 }
-            } else {
-                let new_state = State { path: new_path, pos: new_pos };
-                queue.push_back(new_state);
+            };
+
+            let new_pos = move_.step(pos);
+            write!(self.path, "{}", move_).unwrap();
+
+            if new_pos == self.vault.goal {
+                // Reaching the vault ends the path.
+                let path = self.path.clone();
+                self.path.pop();
+                return Some(path);
             }
+            // Only the new move needs hashing.
+            let mut hasher = hasher;
+            hasher.input(&self.path.as_bytes()[self.path.len() - 1..]);
+            let moves = self.vault.open_doors(hasher, new_pos).into_iter();
+            self.stack.push((new_pos, hasher, moves));
         }
+        None
     }
+}
 
-    if let Some(short) = shortest_path {
-        Some((short, longest_path.unwrap()))
-    } else {
-        None
+fn find_paths_dfs(vault: &Vault<'_>) -> Option<(String, usize)> {
+    let mut shortest_path: Option<String> = None;
+    let mut longest_path = 0;
+
+    for path in Paths::new(vault) {
+        longest_path = longest_path.max(path.len());
+        if shortest_path.as_ref().is_none_or(|shortest| path.len() < shortest.len()) {
+            shortest_path = Some(path);
+        }
     }
+
+    shortest_path.map(|short| (short, longest_path))
 }
 
-pub(crate) fn solve() {
+/// Number of paths of each length.
+fn length_histogram<I: IntoIterator<Item = String>>(paths: I) -> BTreeMap<usize, usize> {
+    let mut histogram = BTreeMap::new();
+    for path in paths {
+        *histogram.entry(path.len()).or_insert(0) += 1;
+    }
+    histogram
+}
+
+pub(crate) fn solve(args: &[&str]) {
+    let matches = App::new("aoc 17")
+        .arg(Arg::with_name("dfs")
+            .long("dfs")
+            .help("Search depth first, using less memory"))
+        .arg(Arg::with_name("width")
+            .long("width")
+            .takes_value(true)
+            .default_value("4")
+            .help("Number of rooms across"))
+        .arg(Arg::with_name("height")
+            .long("height")
+            .takes_value(true)
+            .default_value("4")
+            .help("Number of rooms down"))
+        .arg(Arg::with_name("start")
+            .long("start")
+            .takes_value(true)
+            .validator(|s| grid::parse_pos(&s).map(|_| ()))
+            .help("Room to start in, as X,Y from the top left [default: 0,0]"))
+        .arg(Arg::with_name("goal")
+            .long("goal")
+            .takes_value(true)
+            .validator(|s| grid::parse_pos(&s).map(|_| ()))
+            .help("Room the vault is in [default: the bottom right]"))
+        .arg(Arg::with_name("paths")
+            .long("paths")
+            .help("List every path to the vault"))
+        .arg(Arg::with_name("histogram")
+            .long("histogram")
+            .help("Count the paths to the vault of each length"))
+        .get_matches_from(iter::once("aoc 17").chain(args.iter().cloned()));
+
     let passcode = stdin_as_string();
+    let search = if matches.is_present("dfs") { Search::Dfs } else { Search::Bfs };
 
-    let (shortest_path, longest_path) = find_paths(&passcode).unwrap();
+    let width = clap::value_t!(matches.value_of("width"), i32).unwrap_or_else(|e| e.exit());
+    let height = clap::value_t!(matches.value_of("height"), i32).unwrap_or_else(|e| e.exit());
+    if width < 1 || height < 1 {
+        eprintln!("The vault needs at least one room");
+        process::exit(1);
+    }
+    let mut vault = Vault::new(&passcode, (width, height));
+    if let Some(start) = matches.value_of("start") {
+        vault.start = grid::parse_pos(start).unwrap();
+    }
+    if let Some(goal) = matches.value_of("goal") {
+        vault.goal = grid::parse_pos(goal).unwrap();
+    }
+    for &(x, y) in &[vault.start, vault.goal] {
+        if x >= width || y >= height {
+            eprintln!("{},{} is outside the {}x{} vault", x, y, width, height);
+            process::exit(1);
+        }
+    }
+
+    if matches.is_present("paths") {
+        for path in Paths::new(&vault) {
+            println!("{}", path);
+        }
+        return;
+    }
+    if matches.is_present("histogram") {
+        for (length, count) in length_histogram(Paths::new(&vault)) {
+            println!("{} {}", length, count);
+        }
+        return;
+    }
+
+    let (shortest_path, longest_path) = match find_paths(&vault, search) {
+        Some(paths) => paths,
+        None => {
+            eprintln!("The vault can't be reached");
+            process::exit(1);
+        }
+    };
 
     println!("Part 1: {}", shortest_path);
     println!("Part 2: {}", longest_path);
@@ -132,22 +359,199 @@ mod tests {
 
     #[test]
     fn example1() {
-        let (shortest_path, longest_path) = find_paths("ihgpwlah").unwrap();
-        assert_eq!(&shortest_path, "DDRRRD");
-        assert_eq!(longest_path, 370);
+        for &search in &[Search::Bfs, Search::Dfs] {
+            let (shortest_path, longest_path) = find_paths(&Vault::new("ihgpwlah", (4, 4)), search).unwrap();
+            assert_eq!(&shortest_path, "DDRRRD");
+            assert_eq!(longest_path, 370);
+        }
     }
 
     #[test]
     fn example2() {
-        let (shortest_path, longest_path) = find_paths("kglvqrro").unwrap();
-        assert_eq!(&shortest_path, "DDUDRLRRUDRD");
-        assert_eq!(longest_path, 492);
+        for &search in &[Search::Bfs, Search::Dfs] {
+            let (shortest_path, longest_path) = find_paths(&Vault::new("kglvqrro", (4, 4)), search).unwrap();
+            assert_eq!(&shortest_path, "DDUDRLRRUDRD");
+            assert_eq!(longest_path, 492);
+        }
     }
 
     #[test]
     fn example3() {
-        let (shortest_path, longest_path) = find_paths("ulqzkmiv").unwrap();
-        assert_eq!(&shortest_path, "DRURDRUDDLLDLUURRDULRLDUUDDDRR");
-        assert_eq!(longest_path, 830);
+        for &search in &[Search::Bfs, Search::Dfs] {
+            let (shortest_path, longest_path) = find_paths(&Vault::new("ulqzkmiv", (4, 4)), search).unwrap();
+            assert_eq!(&shortest_path, "DRURDRUDDLLDLUURRDULRLDUUDDDRR");
+            assert_eq!(longest_path, 830);
+        }
+    }
+
+    #[test]
+    fn test_no_path() {
+        // Only down is open from the start. From there, both DR and DUR end
+        // in rooms with every door closed.
+        let vault = Vault::new("hijkl", (4, 4));
+        let moves = |path, pos| {
+            vault.possible_moves(path, pos)
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<String>()
+        };
+        assert_eq!(moves("", (0, 0)), "D");
+        assert_eq!(moves("D", (0, 1)), "UR");
+        assert_eq!(moves("DR", (1, 1)), "");
+        assert_eq!(moves("DU", (0, 0)), "R");
+        assert_eq!(moves("DUR", (1, 0)), "");
+        assert_eq!(find_paths(&vault, Search::Bfs), None);
+        assert_eq!(find_paths(&vault, Search::Dfs), None);
+    }
+
+    #[test]
+    fn test_paths() {
+        let vault = Vault::new("ihgpwlah", (4, 4));
+        let paths = Paths::new(&vault).collect::<Vec<_>>();
+        assert!(paths.contains(&"DDRRRD".to_string()));
+        for path in &paths {
+            assert_eq!(walk(&vault, path), vault.goal);
+        }
+
+        // Every path is found once.
+        let mut unique = paths.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), paths.len());
+
+        let histogram = length_histogram(paths.iter().cloned());
+        assert_eq!(histogram.values().sum::<usize>(), paths.len());
+        assert_eq!(histogram.keys().next(), Some(&6));
+        assert_eq!(histogram.keys().next_back(), Some(&370));
+
+        // Starting in the vault.
+        let single = Vault::new("ihgpwlah", (1, 1));
+        assert_eq!(Paths::new(&single).collect::<Vec<_>>(), vec![""]);
+        assert_eq!(length_histogram(Paths::new(&single)), [(0, 1)].iter().cloned().collect());
+    }
+
+    /// Follows `path` through `vault`, checking every door it goes through
+    /// is open and that it only reaches the vault at the end.
+    fn walk(vault: &Vault<'_>, path: &str) -> Pos {
+        let mut pos = vault.start;
+        for (i, c) in path.char_indices() {
+            assert_ne!(pos, vault.goal, "{:?} goes through the vault", path);
+            let move_ = vault.possible_moves(&path[..i], pos)
+                .into_iter()
+                .find(|move_| move_.to_string() == c.to_string())
+                .unwrap_or_else(|| panic!("{} isn't open after {:?}", c, &path[..i]));
+            pos = move_.step(pos);
+        }
+        pos
+    }
+
+    #[test]
+    fn test_small_vaults() {
+        let sizes = [(1, 1), (2, 2), (3, 3), (5, 2), (1, 6)];
+        for &size in &sizes {
+            for seed in 0..20 {
+                let passcode = format!("vault{}", seed);
+                let (width, height) = size;
+                let mut vault = Vault::new(&passcode, size);
+                vault.start = (seed % width, 0);
+                vault.goal = (width - 1, height - 1 - seed % height);
+
+                let bfs = find_paths(&vault, Search::Bfs);
+                let dfs = find_paths(&vault, Search::Dfs);
+                assert_eq!(bfs, dfs, "{:?}", vault);
+
+                if let Some((shortest_path, longest_path)) = bfs {
+                    assert_eq!(walk(&vault, &shortest_path), vault.goal);
+                    assert!(shortest_path.len() >= grid::manhattan(vault.start, vault.goal));
+                    assert!(longest_path >= shortest_path.len());
+                    assert_eq!(longest_path % 2, shortest_path.len() % 2);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "nightly", test))]
+mod benches {
+    use super::*;
+
+    use test::Bencher;
+
+    /// Door check as it was before hashing incrementally: the passcode and
+    /// whole path hashed in one go, then the hex digest's first four
+    /// characters compared.
+    fn open_doors_hex(passcode: &str, path: &str) -> Vec<bool> {
+        let mut hasher = Md5::new();
+        hasher.input_str(passcode);
+        hasher.input_str(path);
+        hasher.result_str()
+            .chars()
+            .take(4)
+            .map(|c| matches!(c, 'b'..='f'))
+            .collect()
+    }
+
+    #[bench]
+    fn bench_example1(b: &mut Bencher) {
+        let vault = Vault::new("ihgpwlah", (4, 4));
+        b.iter(|| find_paths(&vault, Search::Dfs));
+    }
+
+    #[bench]
+    fn bench_example2(b: &mut Bencher) {
+        let vault = Vault::new("kglvqrro", (4, 4));
+        b.iter(|| find_paths(&vault, Search::Dfs));
+    }
+
+    #[bench]
+    fn bench_example3(b: &mut Bencher) {
+        let vault = Vault::new("ulqzkmiv", (4, 4));
+        b.iter(|| find_paths(&vault, Search::Dfs));
+    }
+
+    // Breadth first hashes each whole path again, for comparison.
+    #[bench]
+    fn bench_example1_bfs(b: &mut Bencher) {
+        let vault = Vault::new("ihgpwlah", (4, 4));
+        b.iter(|| find_paths(&vault, Search::Bfs));
+    }
+
+    #[bench]
+    fn bench_example2_bfs(b: &mut Bencher) {
+        let vault = Vault::new("kglvqrro", (4, 4));
+        b.iter(|| find_paths(&vault, Search::Bfs));
+    }
+
+    #[bench]
+    fn bench_example3_bfs(b: &mut Bencher) {
+        let vault = Vault::new("ulqzkmiv", (4, 4));
+        b.iter(|| find_paths(&vault, Search::Bfs));
+    }
+
+    // A single door check after the longest path in example 1.
+    #[bench]
+    fn bench_doors_hex(b: &mut Bencher) {
+        let path = "DR".repeat(185);
+        b.iter(|| open_doors_hex("ihgpwlah", &path));
+    }
+
+    #[bench]
+    fn bench_doors_nibbles(b: &mut Bencher) {
+        let vault = Vault::new("ihgpwlah", (4, 4));
+        let path = "DR".repeat(185);
+        b.iter(|| vault.possible_moves(&path, (1, 1)));
+    }
+
+    #[bench]
+    fn bench_doors_incremental(b: &mut Bencher) {
+        let vault = Vault::new("ihgpwlah", (4, 4));
+        let mut hasher = vault.prefix;
+        hasher.input_str(&"DR".repeat(184));
+        hasher.input_str("D");
+        b.iter(|| {
+            let mut hasher = hasher;
+            hasher.input_str("R");
+            vault.open_doors(hasher, (1, 1))
+        });
     }
 }
@@ -1,53 +1,547 @@
-use std::io::{self, BufRead};
+use std::collections::{HashMap, VecDeque};
+use std::iter;
+use std::process;
+use std::thread;
+use std::time::Duration;
 
+use clap::{App, Arg};
 use itertools::Itertools;
-use nom::{anychar, space};
+use termion::cursor;
 
-use super::parsers::int32;
+use super::util::stdin_as_string;
 
+type Pos = (i32, i32);
+
+/// Sizes are in megabytes.
 #[derive(Debug)]
 struct Node {
     x: i32,
     y: i32,
-    size: i32,
-    used: i32,
+    size: u64,
+    used: u64,
 }
 
 impl Node {
-    fn available(&self) -> i32 {
+    fn available(&self) -> u64 {
         self.size - self.used
     }
+
+    fn name(&self) -> String {
+        format!("node-x{}-y{}", self.x, self.y)
+    }
 }
 
-named!(node_line<&str, Node>,
-    do_parse!(
-        tag!("/dev/grid/node-x") >>
-        x: int32 >>
-        tag!("-y") >>
-        y: int32 >>
-        space >>
-        size: int32 >>
-        tag!("T") >>
-        space >>
-        used: int32 >>
-        anychar >>
-        (Node { x, y, size, used })
-    )
-);
+/// Positions of the columns named in the header line.
+struct Columns {
+    count: usize,
+    size: usize,
+    used: usize,
+    avail: Option<usize>,
+    use_percent: Option<usize>,
+}
+
+impl Columns {
+    fn new(header: &str) -> Result<Self, String> {
+        let names = header.split_whitespace().collect::<Vec<_>>();
+        let find = |name| names.iter().position(|&n| n == name);
+        Ok(Columns {
+            count: names.len(),
+            size: find("Size").ok_or("no Size column")?,
+            used: find("Used").ok_or("no Used column")?,
+            avail: find("Avail"),
+            use_percent: find("Use%"),
+        })
+    }
+}
+
+/// Parses a human-readable size such as `86T` or `1.5G` into megabytes,
+/// along with the megabytes that one in its last digit is worth, which
+/// bounds how far it was rounded.
+fn parse_size(s: &str) -> Result<(u64, u64), String> {
+    let unit = match s.chars().last() {
+        Some('M') => 1,
+        Some('G') => 1 << 10,
+        Some('T') => 1 << 20,
+        _ => return Err(format!("size {:?} doesn't end in M, G or T", s)),
+    };
+    let number = &s[..s.len() - 1];
+    let (whole, fraction) = match number.find('.') {
+        Some(i) => (&number[..i], &number[i + 1..]),
+        None => (number, ""),
+    };
+
+    let invalid = || format!("invalid size {:?}", s);
+    let digits = |d: &str| !d.is_empty() && d.bytes().all(|b| b.is_ascii_digit());
+    if !digits(whole) || (number.contains('.') && !digits(fraction)) || fraction.len() > 6 {
+        return Err(invalid());
+    }
+
+    let scale = 10u64.pow(fraction.len() as u32);
+    let whole = whole.parse::<u64>().ok()
+        .and_then(|w| w.checked_mul(unit))
+        .ok_or_else(invalid)?;
+    let fraction = if fraction.is_empty() { 0 } else { fraction.parse::<u64>().unwrap() };
+    let megabytes = whole + (fraction * unit + scale / 2) / scale;
+    Ok((megabytes, (unit / scale).max(1)))
+}
+
+fn parse_name(name: &str) -> Result<Pos, String> {
+    let coords = name.strip_prefix("/dev/grid/node-x").and_then(|rest| {
+        let mut parts = rest.splitn(2, "-y");
+        let x = parts.next()?.parse::<u16>().ok()?;
+        let y = parts.next()?.parse::<u16>().ok()?;
+        Some((i32::from(x), i32::from(y)))
+    });
+    coords.ok_or_else(|| format!("unexpected filesystem {:?}", name))
+}
+
+fn parse_node(line: &str, columns: &Columns) -> Result<Node, String> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    if fields.len() != columns.count {
+        return Err(format!("expected {} columns, found {}", columns.count, fields.len()));
+    }
+
+    let (x, y) = parse_name(fields[0])?;
+    let (size, size_error) = parse_size(fields[columns.size])?;
+    let (used, used_error) = parse_size(fields[columns.used])?;
+    if used > size {
+        return Err(format!("used {} is more than size {}", fields[columns.used], fields[columns.size]));
+    }
+
+    if let Some(column) = columns.avail {
+        let (avail, avail_error) = parse_size(fields[column])?;
+        let error = size_error + used_error + avail_error;
+        if avail + error < size - used || avail > size - used + error {
+            return Err(format!("available {} doesn't match size minus used", fields[column]));
+        }
+    }
+
+    if let Some(column) = columns.use_percent {
+        let percent = fields[column].strip_suffix('%')
+            .and_then(|p| p.parse::<u64>().ok())
+            .ok_or_else(|| format!("invalid percentage {:?}", fields[column]))?;
+        // Allow for size and used having been rounded.
+        let min = 100 * used.saturating_sub(used_error) / (size + size_error);
+        let max = match size.saturating_sub(size_error) {
+            0 => 100,
+            smallest => (100 * (used + used_error)).div_ceil(smallest),
+        };
+        if percent < min || percent > max {
+            return Err(format!("use {} doesn't match used divided by size", fields[column]));
+        }
+    }
+
+    Ok(Node { x, y, size, used })
+}
+
+/// Parses the nodes listed after the header line, reporting the line
+/// number of the first that's malformed. Anything before the header, such
+/// as a shell prompt, is ignored.
+fn parse_nodes(input: &str) -> Result<Vec<Node>, String> {
+    let mut lines = input.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|&(_, line)| !line.is_empty())
+        .skip_while(|&(_, line)| !line.starts_with("Filesystem"));
+
+    let (number, header) = lines.next().ok_or("no header line starting with Filesystem")?;
+    let columns = Columns::new(header).map_err(|e| format!("line {}: {}", number, e))?;
+    lines
+        .map(|(number, line)| parse_node(line, &columns).map_err(|e| format!("line {}: {}", number, e)))
+        .collect()
+}
 
 fn is_viable(a: &Node, b: &Node) -> bool {
     a.used > 0 && a.used <= b.available()
 }
 
-pub(crate) fn solve() {
-    let stdin = io::stdin();
-    let nodes = stdin.lock().lines()
-        .skip(2)
-        .map(|l| node_line(&l.unwrap()).to_result().unwrap())
-        .collect::<Vec<_>>();
-    let viable = nodes.iter()
-        .tuple_combinations::<(_, _)>()
-        .filter(|&(a, b)| is_viable(a, b) || is_viable(b, a))
-        .collect::<Vec<_>>();
-    println!("Part 1: {}", viable.len());
+/// Number of pairs of distinct nodes (A, B) where A's data fits on B.
+fn count_viable(nodes: &[Node]) -> usize {
+    let mut available = nodes.iter().map(Node::available).collect::<Vec<_>>();
+    available.sort();
+
+    nodes.iter()
+        .filter(|a| a.used > 0)
+        .map(|a| {
+            let fits = available.len() - available.partition_point(|&avail| avail < a.used);
+            // A node can't be paired with itself.
+            if is_viable(a, a) { fits - 1 } else { fits }
+        })
+        .sum()
+}
+
+/// The cluster reduced to what matters for moving data around. Only the
+/// empty node has room for another node's data, so every move swaps the
+/// empty node with a neighbour that isn't a wall.
+struct Cluster {
+    width: i32,
+    height: i32,
+    /// Nodes with more data than the empty node can hold, indexed by
+    /// `y * width + x`.
+    walls: Vec<bool>,
+    empty: Pos,
+}
+
+impl Cluster {
+    /// Fails unless moving the empty node around is the only way to move
+    /// data: every node's data fits in every other node that isn't a wall,
+    /// walls never move, and no node can ever hold two nodes' data.
+    fn new(nodes: &[Node]) -> Result<Self, String> {
+        let width = nodes.iter().map(|n| n.x).max().ok_or("no nodes")? + 1;
+        let height = nodes.iter().map(|n| n.y).max().unwrap() + 1;
+        let positions = nodes.iter().map(|n| (n.x, n.y)).unique().count();
+        // Coordinates go up to 65535, so the area may not fit in an i32.
+        if positions != nodes.len() || nodes.len() as i64 != i64::from(width) * i64::from(height) {
+            return Err("nodes don't form a grid".to_string());
+        }
+
+        let empty = nodes.iter().filter(|n| n.used == 0).collect::<Vec<_>>();
+        if empty.len() != 1 {
+            return Err(format!("expected exactly one empty node, found {}", empty.len()));
+        }
+        let empty = empty[0];
+
+        let (walls, open): (Vec<&Node>, Vec<&Node>) = nodes.iter().partition(|n| n.used > empty.size);
+
+        let most_data = open.iter().max_by_key(|n| n.used).unwrap();
+        let smallest = open.iter().min_by_key(|n| n.size).unwrap();
+        if most_data.used > smallest.size {
+            return Err(format!(
+                "data on {} doesn't fit on {}, which the empty node can move to",
+                most_data.name(),
+                smallest.name()
+            ));
+        }
+
+        let largest = open.iter().max_by_key(|n| n.size).unwrap();
+        if let Some(wall) = walls.iter().find(|n| n.used <= largest.size) {
+            return Err(format!(
+                "data on {} doesn't fit on {} but does fit on {}",
+                wall.name(),
+                empty.name(),
+                largest.name()
+            ));
+        }
+
+        // Only data from open nodes moves. A wall can never take any of it,
+        // and an open node can only take it if its own data is moved out.
+        let data = open.iter().map(|n| n.used).filter(|&used| used > 0).sorted();
+        if data.len() >= 2 && largest.size >= data[0] + data[1] {
+            return Err(format!("{} can hold data from two nodes", largest.name()));
+        }
+        if let Some(wall) = walls.iter().find(|n| data.first().is_some_and(|&d| n.available() >= d)) {
+            return Err(format!("{} has room for another node's data", wall.name()));
+        }
+
+        let mut is_wall = vec![false; nodes.len()];
+        for node in &walls {
+            is_wall[(node.y * width + node.x) as usize] = true;
+        }
+
+        Ok(Cluster {
+            width,
+            height,
+            walls: is_wall,
+            empty: (empty.x, empty.y),
+        })
+    }
+
+    fn is_wall(&self, pos: Pos) -> bool {
+        let (x, y) = pos;
+        self.walls[(y * self.width + x) as usize]
+    }
+
+    fn neighbours(&self, pos: Pos) -> Vec<Pos> {
+        let (x, y) = pos;
+        let neighbours = [(x + 1, y), (x, y - 1), (x - 1, y), (x, y + 1)];
+
+        neighbours.iter()
+            .cloned()
+            .filter(|&(x, y)| x >= 0 && y >= 0 && x < self.width && y < self.height)
+            .filter(|p| !self.is_wall(*p))
+            .collect::<Vec<Pos>>()
+    }
+
+    /// Shortest sequence of (empty node, goal data) positions that gets the
+    /// data at `goal` to the node at (0, 0), including the starting one.
+    fn migrate(&self, goal: Pos) -> Option<Vec<(Pos, Pos)>> {
+        let start = (self.empty, goal);
+        let mut came_from: HashMap<(Pos, Pos), (Pos, Pos)> = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+
+        while let Some(current) = queue.pop_front() {
+            let (empty, goal) = current;
+            if goal == (0, 0) {
+                let mut path = vec![current];
+                let mut state = current;
+                while state != start {
+                    state = came_from[&state];
+                    path.push(state);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for neighbour in self.neighbours(empty) {
+                // Moving the goal data into the empty node leaves the goal's
+                // old node empty.
+                let state = if neighbour == goal {
+                    (goal, empty)
+                } else {
+                    (neighbour, goal)
+                };
+                if state != start && !came_from.contains_key(&state) {
+                    came_from.insert(state, current);
+                    queue.push_back(state);
+                }
+            }
+        }
+        None
+    }
+
+    /// Draws the empty node as `_`, walls as `#`, the goal data as `G`, and
+    /// every other node as `.`.
+    fn render(&self, empty: Pos, goal: Pos) -> String {
+        (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| match (x, y) {
+                        pos if pos == empty => '_',
+                        pos if pos == goal => 'G',
+                        pos if self.is_wall(pos) => '#',
+                        _ => '.',
+                    })
+                    .collect::<String>()
+            })
+            .join("\n")
+    }
+}
+
+pub(crate) fn solve(args: &[&str]) {
+    let matches = App::new("aoc 22")
+        .arg(Arg::with_name("show")
+            .long("show")
+            .help("Draw the cluster before moving any data"))
+        .arg(Arg::with_name("animate")
+            .long("animate")
+            .help("Redraw the cluster after every move"))
+        .arg(Arg::with_name("delay")
+            .long("delay")
+            .takes_value(true)
+            .default_value("50")
+            .help("Milliseconds between frames when animating"))
+        .get_matches_from(iter::once("aoc 22").chain(args.iter().cloned()));
+
+    let nodes = parse_nodes(&stdin_as_string()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    println!("Part 1: {}", count_viable(&nodes));
+
+    let cluster = Cluster::new(&nodes).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let goal = (cluster.width - 1, 0);
+    if matches.is_present("show") {
+        println!("{}", cluster.render(cluster.empty, goal));
+    }
+
+    let path = cluster.migrate(goal).unwrap_or_else(|| {
+        eprintln!("The goal data can't be moved to node-x0-y0");
+        process::exit(1);
+    });
+    if matches.is_present("animate") {
+        let delay = clap::value_t!(matches.value_of("delay"), u64).unwrap_or_else(|e| e.exit());
+        for (step, &(empty, goal)) in path.iter().enumerate() {
+            if step > 0 {
+                thread::sleep(Duration::from_millis(delay));
+                print!("{}\r", cursor::Up(cluster.height as u16 + 1));
+            }
+            println!("Step {}/{}", step, path.len() - 1);
+            println!("{}", cluster.render(empty, goal));
+        }
+    }
+    println!("Part 2: {}", path.len() - 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::util::{compare_brute_force, Random};
+
+    fn min_steps(cluster: &Cluster, goal: Pos) -> Option<usize> {
+        cluster.migrate(goal).map(|path| path.len() - 1)
+    }
+
+    const EXAMPLE: &str = "\
+        Filesystem            Size  Used  Avail  Use%
+        /dev/grid/node-x0-y0   10T    8T     2T   80%
+        /dev/grid/node-x0-y1   11T    6T     5T   54%
+        /dev/grid/node-x0-y2   32T   28T     4T   87%
+        /dev/grid/node-x1-y0    9T    7T     2T   77%
+        /dev/grid/node-x1-y1    8T    0T     8T    0%
+        /dev/grid/node-x1-y2   11T    7T     4T   63%
+        /dev/grid/node-x2-y0   10T    6T     4T   60%
+        /dev/grid/node-x2-y1    9T    8T     1T   88%
+        /dev/grid/node-x2-y2    9T    6T     3T   66%";
+
+    #[test]
+    fn example() {
+        let cluster = Cluster::new(&parse_nodes(EXAMPLE).unwrap()).unwrap();
+        assert_eq!(cluster.empty, (1, 1));
+        assert!(cluster.is_wall((0, 2)));
+        assert_eq!(min_steps(&cluster, (2, 0)), Some(7));
+    }
+
+    #[test]
+    fn test_parse() {
+        let nodes = parse_nodes("\
+            root@ebhq-gridcenter# df -h
+            Filesystem              Size  Used  Avail  Use%
+            /dev/grid/node-x0-y0     86T   73T    13T   84%
+            /dev/grid/node-x12-y3   1.5T  512G   1.0T   34%
+            /dev/grid/node-x1-y0    900M    0M   900M    0%").unwrap();
+        assert_eq!(nodes.len(), 3);
+        assert_eq!((nodes[0].x, nodes[0].y, nodes[0].size, nodes[0].used), (0, 0, 86 << 20, 73 << 20));
+        assert_eq!((nodes[1].x, nodes[1].y, nodes[1].size, nodes[1].used), (12, 3, 3 << 19, 512 << 10));
+        assert_eq!(nodes[2].available(), 900);
+
+        // Columns are found by name.
+        let nodes = parse_nodes("\
+            Filesystem Used Size
+            /dev/grid/node-x0-y0 2G 3G").unwrap();
+        assert_eq!((nodes[0].size, nodes[0].used), (3 << 10, 2 << 10));
+
+        let header = "Filesystem Size Used Avail Use%\n";
+        let errors = vec![
+            ("/dev/grid/node-x0-y0 86T 73T 13T", "line 2: expected 5 columns, found 4"),
+            ("/dev/grid/node-x0-y0 86X 73T 13T 84%", "line 2: size \"86X\" doesn't end in M, G or T"),
+            ("/dev/grid/node-x0-y0 8.T 7T 1T 84%", "line 2: invalid size \"8.T\""),
+            ("/dev/sda1 86T 73T 13T 84%", "line 2: unexpected filesystem \"/dev/sda1\""),
+            ("/dev/grid/node-x0-y0 73T 86T 13T 84%", "line 2: used 86T is more than size 73T"),
+            ("/dev/grid/node-x0-y0 86T 73T 30T 84%", "line 2: available 30T doesn't match size minus used"),
+            ("/dev/grid/node-x0-y0 86T 73T 13T 50%", "line 2: use 50% doesn't match used divided by size"),
+        ];
+        for (line, error) in errors {
+            assert_eq!(parse_nodes(&format!("{}{}", header, line)).unwrap_err(), error);
+        }
+        assert_eq!(parse_nodes("Filesystem Size\n").unwrap_err(), "line 1: no Used column");
+        assert!(parse_nodes("/dev/grid/node-x0-y0 86T 73T 13T 84%").is_err());
+    }
+
+    #[test]
+    fn test_count_viable() {
+        assert_eq!(count_viable(&parse_nodes(EXAMPLE).unwrap()), 7);
+
+        // Compare against checking every pair on small generated clusters.
+        // Sizes are small enough that many pairs of nodes each fit on the
+        // other, as well as nodes that fit on themselves.
+        let generate = |random: &mut Random| {
+            let len = random.below(60) + 2;
+            (0..len as i32)
+                .map(|i| {
+                    let size = random.below(20) + 1;
+                    let used = random.below(size + 1);
+                    Node { x: i % 10, y: i / 10, size, used }
+                })
+                .collect::<Vec<_>>()
+        };
+        let brute_force = |nodes: &Vec<Node>| {
+            nodes.iter()
+                .enumerate()
+                .cartesian_product(nodes.iter().enumerate())
+                .filter(|&((i, a), (j, b))| i != j && is_viable(a, b))
+                .count()
+        };
+        compare_brute_force(200, 0x2545_f491, generate, |nodes| count_viable(nodes), brute_force);
+    }
+
+    #[test]
+    fn test_count_viable_large() {
+        // 100,000 nodes of size 100, with 1000 using each amount from 0 to 99.
+        // Data using u fits on every node using at most 100 - u, apart from
+        // itself when u is at most 50.
+        let nodes = (0..100_000)
+            .map(|i| Node { x: i % 1000, y: i / 1000, size: 100, used: (i % 100) as u64 })
+            .collect::<Vec<_>>();
+        let expected = (1..100)
+            .map(|used| 1000 * (1000 * (101 - used) - if used <= 50 { 1 } else { 0 }))
+            .sum::<usize>();
+        assert_eq!(count_viable(&nodes), expected);
+    }
+
+    #[test]
+    fn test_render() {
+        let cluster = Cluster::new(&parse_nodes(EXAMPLE).unwrap()).unwrap();
+        assert_eq!(cluster.render(cluster.empty, (2, 0)), "..G\n._.\n#..");
+
+        let path = cluster.migrate((2, 0)).unwrap();
+        let &(empty, goal) = path.last().unwrap();
+        assert_eq!(cluster.render(empty, goal), "G_.\n...\n#..");
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let grid = |rows: &[&[(u64, u64)]]| {
+            let mut nodes = Vec::new();
+            for (y, row) in rows.iter().enumerate() {
+                for (x, &(size, used)) in row.iter().enumerate() {
+                    nodes.push(Node { x: x as i32, y: y as i32, size, used });
+                }
+            }
+            nodes
+        };
+        let error = |rows: &[&[(u64, u64)]]| Cluster::new(&grid(rows)).err();
+
+        // The goal data can't fit on the node at (0, 0), however the empty
+        // node moves.
+        assert_eq!(
+            error(&[&[(20, 12), (100, 0), (60, 50)], &[(20, 15), (20, 15), (20, 15)]]),
+            Some("data on node-x2-y0 doesn't fit on node-x0-y0, which the empty node can move to".to_string())
+        );
+        assert_eq!(
+            error(&[&[(10, 7), (10, 0), (10, 0)]]),
+            Some("expected exactly one empty node, found 2".to_string())
+        );
+        assert_eq!(
+            error(&[&[(10, 7), (10, 0), (20, 15)], &[(16, 7), (10, 7), (10, 7)]]),
+            Some("data on node-x2-y0 doesn't fit on node-x1-y0 but does fit on node-x0-y1".to_string())
+        );
+        assert_eq!(
+            error(&[&[(10, 3), (12, 0), (10, 4)]]),
+            Some("node-x1-y0 can hold data from two nodes".to_string())
+        );
+        assert_eq!(
+            error(&[&[(10, 7), (10, 0), (100, 20)]]),
+            Some("node-x2-y0 has room for another node's data".to_string())
+        );
+        assert_eq!(error(&[&[(10, 7), (10, 0)], &[(10, 7)]]), Some("nodes don't form a grid".to_string()));
+        let corners = vec![
+            Node { x: 65535, y: 0, size: 10, used: 7 },
+            Node { x: 0, y: 65535, size: 10, used: 0 },
+        ];
+        assert_eq!(Cluster::new(&corners).err(), Some("nodes don't form a grid".to_string()));
+    }
+
+    #[test]
+    fn test_walls() {
+        // A wall across row 2 forces the empty node round through column 0.
+        let mut nodes = Vec::new();
+        for y in 0..4 {
+            for x in 0..5 {
+                let (size, used) = match (x, y) {
+                    (4, 3) => (10, 0),
+                    (1..=4, 2) => (100, 95),
+                    _ => (10, 7),
+                };
+                nodes.push(Node { x, y, size, used });
+            }
+        }
+        let cluster = Cluster::new(&nodes).unwrap();
+        // 10 moves to get next to the goal, one to swap with it, then 5 for
+        // each of the remaining columns.
+        assert_eq!(min_steps(&cluster, (4, 0)), Some(10 + 1 + 5 * 3));
+    }
 }
@@ -1,22 +1,46 @@
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, BufReader};
+use std::iter;
 
+use clap::{App, Arg};
 use maplit::hashmap;
 
-use crate::assembunny;
+use crate::assembunny::debugger::Debugger;
+use crate::assembunny::{self, Arithmetic, Halt, Options, Program, Vm};
+
+pub(crate) fn solve(args: &[&str]) {
+    let matches = App::new("aoc 23")
+        .arg(Arg::with_name("debug")
+            .long("debug")
+            .help("Step through part 1 interactively"))
+        .get_matches_from(iter::once("aoc 23").chain(args.iter().cloned()));
 
-pub(crate) fn solve() {
     let stdin = io::stdin();
     let instructions = stdin.lock().lines()
         .map(|l| l.unwrap())
         .map(|l| assembunny::instruction(&l).to_result().unwrap())
         .collect::<Vec<_>>();
 
-    let mut registers1 = hashmap!{'a' => 7};
-    let mut registers2 = hashmap!{'a' => 12};
+    // a! grows quickly, so use 64-bit registers and check for overflow.
+    let mut registers1 = hashmap!{'a' => 7i64};
+    let mut registers2 = hashmap!{'a' => 12i64};
+    let options = Options {
+        arithmetic: Arithmetic::Checked,
+        ..Default::default()
+    };
+
+    if matches.is_present("debug") {
+        // The program was read from stdin, so read commands from the terminal.
+        let tty = termion::get_tty().expect("a terminal to read commands from");
+        let vm = Vm::new(Program::new(instructions), registers1, options);
+        Debugger::new(vm, 1000).repl(BufReader::new(tty), io::stdout()).unwrap();
+        return;
+    }
 
-    assembunny::run(instructions.clone(), &mut registers1, None);
+    let halt = assembunny::run(instructions.clone(), &mut registers1, options);
+    assert_eq!(halt, Halt::Finished);
     println!("Part 1: {}", &registers1[&'a']);
 
-    assembunny::run(instructions, &mut registers2, None);
+    let halt = assembunny::run(instructions, &mut registers2, options);
+    assert_eq!(halt, Halt::Finished);
     println!("Part 2: {}", &registers2[&'a']);
 }
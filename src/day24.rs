@@ -1,13 +1,16 @@
-#![allow(dead_code, unused_imports, unused_variables, unused_mut)]
-
 use itertools::Itertools;
-use maplit::hashmap;
-use permutohedron::Heap;
 use std::char;
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::collections::VecDeque;
 use std::fmt::{self, Debug, Display};
-use std::io::{self, BufRead};
+use std::io;
+use std::iter;
+use std::process;
+
+use clap::{App, Arg};
+use termion::{color, style};
+
+use super::grid::{self, Graph, Pos};
+use super::util::stdin_as_string;
 
 #[derive(Clone, Copy, Debug)]
 enum Block {
@@ -16,8 +19,6 @@ enum Block {
     Number(i32),
 }
 
-type Pos = (i32, i32);
-
 struct Grid<T> {
     grid: Vec<Vec<T>>,
 }
@@ -47,6 +48,16 @@ impl<T> Grid<T> {
         let (x, y) = pos;
         x >= 0 && y >= 0 && x < self.grid[0].len() as i32 && y < self.grid.len() as i32
     }
+
+    fn width(&self) -> usize {
+        self.grid[0].len()
+    }
+
+    /// Index of `pos` in a flat `Vec` with a row after row layout.
+    fn index(&self, pos: Pos) -> usize {
+        let (x, y) = pos;
+        y as usize * self.width() + x as usize
+    }
 }
 
 impl Grid<Block> {
@@ -57,11 +68,47 @@ impl Grid<Block> {
         }
     }
 
-    fn neighbours(&self, pos: Pos) -> Vec<Pos> {
-        let (x, y) = pos;
-        let neighbours = [(x + 1, y), (x, y - 1), (x - 1, y), (x, y + 1)];
+    /// The numbers on the grid and their positions, in ascending order.
+    fn numbers(&self) -> Vec<(i32, Pos)> {
+        let mut numbers = Vec::new();
+        for (y, row) in self.grid.iter().enumerate() {
+            for (x, block) in row.iter().enumerate() {
+                if let Block::Number(n) = block {
+                    numbers.push((*n, (x as i32, y as i32)));
+                }
+            }
+        }
+        numbers.sort();
+        numbers
+    }
+
+    /// Steps from `start` to every position, laid out as by `Grid::index`,
+    /// or `None` for positions that can't be reached.
+    fn distances_from(&self, start: Pos) -> Vec<Option<usize>> {
+        let mut distances = vec![None; self.width() * self.grid.len()];
+        let mut queue = VecDeque::new();
+        distances[self.index(start)] = Some(0);
+        queue.push_back(start);
+
+        while let Some(current) = queue.pop_front() {
+            let steps = distances[self.index(current)].unwrap() + 1;
+            for neighbour in self.neighbours(&current) {
+                let i = self.index(neighbour);
+                if distances[i].is_none() {
+                    distances[i] = Some(steps);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        distances
+    }
+}
 
-        neighbours
+impl Graph for Grid<Block> {
+    type Node = Pos;
+
+    fn neighbours(&self, pos: &Pos) -> Vec<Pos> {
+        grid::adjacent(*pos)
             .iter()
             .cloned()
             .filter(|p| self.in_bounds(*p))
@@ -86,175 +133,286 @@ impl Display for Block {
     }
 }
 
-struct PriorityQueue<V: Debug, P: Debug + Ord = i32> {
-    heap: BinaryHeap<PriorityItem<V, P>>,
-}
-
-impl<V: Debug, P: Debug + Ord> PriorityQueue<V, P> {
-    fn new() -> Self {
-        Self {
-            heap: BinaryHeap::new(),
-        }
-    }
-
-    fn put(&mut self, item: V, priority: P) {
-        self.heap.push(PriorityItem::new(item, priority));
-    }
-
-    fn get(&mut self) -> Option<V> {
-        self.heap.pop().map(|pi| pi.value)
-    }
+/// Steps between each pair of numbers, searching once from each.
+fn pair_distances(grid: &Grid<Block>, numbers: &[(i32, Pos)]) -> Result<Vec<Vec<usize>>, String> {
+    numbers
+        .iter()
+        .map(|&(a, start)| {
+            let distances = grid.distances_from(start);
+            numbers
+                .iter()
+                .map(|&(b, end)| {
+                    distances[grid.index(end)]
+                        .ok_or_else(|| format!("{} can't be reached from {}", b, a))
+                }).collect()
+        }).collect()
 }
 
-#[derive(Debug)]
-struct PriorityItem<V: Debug, P: Debug + Ord> {
-    value: V,
-    priority: P,
+fn parse_grid(input: &str) -> Grid<Block> {
+    let rows = input
+        .lines()
+        .map(|line| {
+            line.trim()
+                .chars()
+                .map(|c| match c {
+                    '#' => Block::Wall,
+                    '.' => Block::Path,
+                    c @ '0'..='9' => Block::Number(c.to_digit(10).unwrap() as i32),
+                    c => panic!(format!("Unexpected character: {}", c)),
+                }).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+    Grid { grid: rows }
 }
 
-impl<V: Debug, P: Debug + Ord> PriorityItem<V, P> {
-    fn new(value: V, priority: P) -> Self {
-        PriorityItem { value, priority }
+/// Shortest route from point 0 through every other point, returning to
+/// point 0 at the end if `return_to_start`, using the Held-Karp algorithm.
+/// `distances[a][b]` is the number of steps from point `a` to point `b`.
+///
+/// Returns the number of steps and the order the points are visited in.
+fn shortest_route(distances: &[Vec<usize>], return_to_start: bool) -> (usize, Vec<usize>) {
+    let n = distances.len();
+    if n <= 1 {
+        let order = if return_to_start { vec![0, 0] } else { vec![0] };
+        return (0, order);
     }
-}
 
-impl<V: Debug, P: Debug + Ord> Eq for PriorityItem<V, P> {}
+    // Point i + 1 is bit i of a mask, since the route always starts at 0.
+    let others = n - 1;
+    let full = (1 << others) - 1;
 
-impl<V: Debug, P: Debug + Ord> PartialEq for PriorityItem<V, P> {
-    fn eq(&self, other: &Self) -> bool {
-        self.priority == other.priority
+    // best[mask * others + last] is the fewest steps from 0 that visit the
+    // points in mask and end at last, and parent is the point before last.
+    let mut best = vec![usize::MAX; (full + 1) * others];
+    let mut parent = vec![0; best.len()];
+    for last in 0..others {
+        best[(1 << last) * others + last] = distances[0][last + 1];
     }
-}
 
-impl<V: Debug, P: Debug + Ord> PartialOrd for PriorityItem<V, P> {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+    for mask in 1..=full {
+        for last in 0..others {
+            let steps = best[mask * others + last];
+            if steps == usize::MAX {
+                continue;
+            }
+            for next in (0..others).filter(|next| mask & 1 << next == 0) {
+                let i = (mask | 1 << next) * others + next;
+                let new_steps = steps + distances[last + 1][next + 1];
+                if new_steps < best[i] {
+                    best[i] = new_steps;
+                    parent[i] = last;
+                }
+            }
+        }
     }
-}
 
-impl<V: Debug, P: Debug + Ord> Ord for PriorityItem<V, P> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        // make this a min heap
-        match self.priority.cmp(&other.priority) {
-            Ordering::Less => Ordering::Greater,
-            Ordering::Equal => Ordering::Equal,
-            Ordering::Greater => Ordering::Less,
+    let total = |last: usize| {
+        let back = if return_to_start { distances[last + 1][0] } else { 0 };
+        best[full * others + last] + back
+    };
+    let mut last = (0..others).min_by_key(|&last| total(last)).unwrap();
+    let steps = total(last);
+
+    let mut order = if return_to_start { vec![0] } else { Vec::new() };
+    let mut mask = full;
+    loop {
+        order.push(last + 1);
+        let prev = parent[mask * others + last];
+        mask &= !(1 << last);
+        if mask == 0 {
+            break;
         }
+        last = prev;
     }
+    order.push(0);
+    order.reverse();
+    (steps, order)
 }
 
-fn heuristic(a: Pos, b: Pos) -> i32 {
-    let (x1, y1) = a;
-    let (x2, y2) = b;
-    (x1 - x2).abs() + (y1 - y2).abs()
-}
-
-fn a_star_search(grid: &Grid<Block>, start: Pos, goal: Pos) -> Vec<Pos> {
-    let mut frontier = PriorityQueue::new();
-    frontier.put(start, 0);
-    let mut came_from = HashMap::new();
-    let mut cost_so_far = HashMap::new();
-    came_from.insert(start, None);
-    cost_so_far.insert(start, 0);
-
-    while let Some(current) = frontier.get() {
-        if current == goal {
-            break;
+/// Draws the grid with a route overlaid, visiting `numbers` in `order`.
+/// Each step is drawn as an arrow in the cell it leaves, and with `colour`
+/// each leg between two numbers gets its own colour. Cells that more than
+/// one leg leaves are drawn as `*`, so that no leg's arrows are hidden.
+fn render_route(grid: &Grid<Block>, numbers: &[(i32, Pos)], order: &[usize], colour: bool) -> String {
+    // The (arrow, leg) of every step leaving each cell.
+    let mut overlay = vec![Vec::new(); grid.width() * grid.grid.len()];
+    for (leg, stops) in order.windows(2).enumerate() {
+        let (start, end) = (numbers[stops[0]].1, numbers[stops[1]].1);
+        let heuristic = |&pos: &Pos| grid::manhattan(pos, end);
+        let path = grid::a_star(grid, start, |&pos| pos == end, heuristic, None)
+            .expect("route between unreachable numbers");
+        for step in path.nodes.windows(2) {
+            let ((x1, y1), (x2, y2)) = (step[0], step[1]);
+            let arrow = match (x2 - x1, y2 - y1) {
+                (1, 0) => '>',
+                (-1, 0) => '<',
+                (0, 1) => 'v',
+                _ => '^',
+            };
+            overlay[grid.index(step[0])].push((arrow, leg));
         }
+    }
 
-        for neighbour in grid.neighbours(current) {
-            // We don't have different costs on the grid, use 1
-            let new_cost = cost_so_far[&current] + 1;
-            if !cost_so_far.contains_key(&neighbour) || new_cost < cost_so_far[&neighbour] {
-                cost_so_far.insert(neighbour, new_cost);
-                let priority = new_cost + heuristic(goal, neighbour);
-                frontier.put(neighbour, priority);
-                came_from.insert(neighbour, Some(current));
+    let mut output = String::new();
+    let mut shared = false;
+    for (y, row) in grid.grid.iter().enumerate() {
+        for (x, block) in row.iter().enumerate() {
+            let overlaid = &overlay[grid.index((x as i32, y as i32))][..];
+            match (*block, overlaid) {
+                (Block::Number(_), _) if colour => {
+                    output += &format!("{}{}{}", style::Bold, block, style::Reset);
+                }
+                (Block::Path, &[(arrow, leg)]) if colour => {
+                    let leg_colour = color::AnsiValue(1 + (leg % 6) as u8);
+                    output += &format!("{}{}{}", color::Fg(leg_colour), arrow, color::Fg(color::Reset));
+                }
+                (Block::Path, &[(arrow, _)]) => output.push(arrow),
+                (Block::Path, &[_, _, ..]) => {
+                    shared = true;
+                    if colour {
+                        output += &format!("{}*{}", style::Bold, style::Reset);
+                    } else {
+                        output.push('*');
+                    }
+                }
+                _ => output.push(char::from(*block)),
             }
         }
+        output.push('\n');
     }
 
-    let mut current = goal;
-
-    // construct a vector of positions to move to
-    let mut path = vec![current];
+    if shared {
+        output += "* is left by more than one leg\n";
+    }
+    let visits = order.iter().map(|&i| numbers[i].0.to_string()).join(" -> ");
+    output + &format!("Visit order: {}", visits)
+}
 
-    while let Some(prev) = came_from[&current] {
-        // we don't want the start position included in the path
-        if prev == start {
-            break;
+pub(crate) fn solve(args: &[&str]) {
+    let matches = App::new("aoc 24")
+        .arg(Arg::with_name("render")
+            .long("render")
+            .help("Draw each part's route on the map"))
+        .get_matches_from(iter::once("aoc 24").chain(args.iter().cloned()));
+
+    let grid = parse_grid(&stdin_as_string());
+
+    // Point i is the i-th smallest number, so 0 is the start.
+    let numbers = grid.numbers();
+    let distances = pair_distances(&grid, &numbers).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let colour = termion::is_tty(&io::stdout());
+    for (part, &return_to_start) in [false, true].iter().enumerate() {
+        let (steps, order) = shortest_route(&distances, return_to_start);
+        println!("Part {}: {}", part + 1, steps);
+        if matches.is_present("render") {
+            println!("{}", render_route(&grid, &numbers, &order, colour));
         }
-        current = prev;
-        path.push(current);
     }
-    path.reverse();
-    path
 }
 
-pub(crate) fn solve() {
-    let stdin = io::stdin();
-    let rows = stdin
-        .lock()
-        .lines()
-        .map(|l| l.unwrap())
-        .map(|line| {
-            line.chars()
-                .map(|c| match c {
-                    '#' => Block::Wall,
-                    '.' => Block::Path,
-                    c @ '0'..='9' => Block::Number(c.to_digit(10).unwrap() as i32),
-                    c => panic!(format!("Unexpected character: {}", c)),
-                }).collect::<Vec<_>>()
-        }).collect::<Vec<_>>();
-
-    let mut number_positions: HashMap<i32, Pos> = HashMap::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use permutohedron::Heap;
+
+    use crate::util::{compare_brute_force, Random};
+
+    const EXAMPLE: &str = "\
+        ###########
+        #0.1.....2#
+        #.#######.#
+        #4.......3#
+        ###########";
+
+    #[test]
+    fn example() {
+        let grid = parse_grid(EXAMPLE);
+        let numbers = grid.numbers();
+        assert_eq!(numbers[0], (0, (1, 1)));
+        let distances = pair_distances(&grid, &numbers).unwrap();
+        assert_eq!(distances[0], vec![0, 2, 8, 10, 2]);
+        assert_eq!(shortest_route(&distances, false), (14, vec![0, 4, 1, 2, 3]));
+    }
 
-    for (y, row) in rows.iter().enumerate() {
-        for (x, block) in row.iter().enumerate() {
-            if let Block::Number(n) = block {
-                number_positions.insert(*n, (x as i32, y as i32));
-            }
-        }
+    #[test]
+    fn test_render_route() {
+        let grid = parse_grid(EXAMPLE);
+        let rendered = render_route(&grid, &grid.numbers(), &[0, 4, 1, 2, 3], false);
+        assert_eq!(rendered, "\
+###########
+#0>1>>>>>2#
+#*#######v#
+#4.......3#
+###########
+* is left by more than one leg
+Visit order: 0 -> 4 -> 1 -> 2 -> 3");
+
+        // No cell is left twice going along the bottom.
+        let rendered = render_route(&grid, &grid.numbers(), &[0, 1, 2, 3, 4], false);
+        assert_eq!(rendered, "\
+###########
+#0>1>>>>>2#
+#.#######v#
+#4<<<<<<<3#
+###########
+Visit order: 0 -> 1 -> 2 -> 3 -> 4");
     }
 
-    let grid = Grid { grid: rows };
-
-    // create a map of position pairs to the number of steps between them.
-    let mut pair_steps = HashMap::new();
-    number_positions
-        .keys()
-        .tuple_combinations::<(_, _)>()
-        .for_each(|(a, b)| {
-            let path = a_star_search(&grid, number_positions[&a], number_positions[&b]);
-            let distance = path.len();
-            pair_steps.insert((*a, *b), distance);
-            pair_steps.insert((*b, *a), distance);
-        });
-
-    let mut numbers = number_positions
-        .keys()
-        .filter(|num| **num != 0)
-        .collect::<Vec<_>>();
-
-    let mut part1_steps = Vec::new();
-    let mut part2_steps = Vec::new();
-
-    // generate each permutation of nodes to visit after zero.
-    let heap = Heap::new(&mut numbers);
-    for data in heap {
-        // First get the number of steps from 0 to the first number.
-        let mut steps = pair_steps[&(0, *data[0])];
-
-        let mut iter = data.windows(2);
-        while let Some([a, b]) = iter.next() {
-            let distance = pair_steps[&(**a, **b)];
-            steps += distance;
-        }
-        let part2_extra = pair_steps[&(**data.last().unwrap(), 0)];
-        part1_steps.push(steps);
-        part2_steps.push(steps + part2_extra)
+    #[test]
+    fn test_unreachable() {
+        let grid = parse_grid("\
+            #####
+            #0#1#
+            #####");
+        let error = pair_distances(&grid, &grid.numbers()).unwrap_err();
+        assert_eq!(error, "1 can't be reached from 0");
     }
 
-    println!("Part 1: {}", part1_steps.iter().min().unwrap());
-    println!("Part 2: {}", part2_steps.iter().min().unwrap());
+    #[test]
+    fn test_shortest_route() {
+        assert_eq!(shortest_route(&[vec![0]], false), (0, vec![0]));
+        assert_eq!(shortest_route(&[vec![0]], true), (0, vec![0, 0]));
+
+        // Compare against trying every order on generated distances.
+        let generate = |random: &mut Random| {
+            let n = random.below(6) as usize + 2;
+            let distances = (0..n)
+                .map(|_| (0..n).map(|_| random.below(50) as usize + 1).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            (distances, random.below(2) == 1)
+        };
+        let length = |distances: &[Vec<usize>], order: &[usize]| {
+            order.windows(2).map(|w| distances[w[0]][w[1]]).sum::<usize>()
+        };
+        let solve = |&(ref distances, return_to_start): &(Vec<Vec<usize>>, bool)| {
+            let n = distances.len();
+            let (steps, order) = shortest_route(distances, return_to_start);
+            assert_eq!(length(distances, &order), steps);
+            assert_eq!(order[0], 0);
+            let mut visited = order.clone();
+            visited.sort();
+            visited.dedup();
+            assert_eq!(visited.len(), n);
+            assert_eq!(order.len(), if return_to_start { n + 1 } else { n });
+            steps
+        };
+        let brute_force = |&(ref distances, return_to_start): &(Vec<Vec<usize>>, bool)| {
+            let mut middle = (1..distances.len()).collect::<Vec<_>>();
+            Heap::new(&mut middle)
+                .map(|middle| {
+                    let mut order = vec![0];
+                    order.extend(middle);
+                    if return_to_start {
+                        order.push(0);
+                    }
+                    length(distances, &order)
+                }).min()
+                .unwrap()
+        };
+        compare_brute_force(50, 0x9e37_79b9, generate, solve, brute_force);
+    }
 }
@@ -1,8 +1,6 @@
 use std::io::{self, BufRead};
 
-use maplit::hashmap;
-
-use crate::assembunny;
+use crate::assembunny::{self, symbolic, Program, Vm};
 
 pub(crate) fn solve() {
     let stdin = io::stdin();
@@ -13,11 +11,15 @@ pub(crate) fn solve() {
         .map(|l| assembunny::instruction(&l).to_result().unwrap())
         .collect::<Vec<_>>();
 
-    for a in 1.. {
-        let mut registers = hashmap!{'a' => a};
-        if assembunny::run(instructions.clone(), &mut registers, Some(100)) {
-            println!("Part 1: {}", a);
-            break;
-        }
-    }
+    let program = Program::new(instructions);
+
+    // Fall back to checking the first 100 values of the clock signal are
+    // 0, 1, 0, 1, ... if the program can't be solved symbolically. Each value
+    // has to be sent within a million steps of the last.
+    let a = symbolic::min_clock_input(&program, 'a').or_else(|| {
+        assembunny::find_min_input(&program, 'a', Default::default(), |vm: &mut Vm<i64>| {
+            (0..100).all(|i| vm.next_output(1_000_000) == Ok(i % 2))
+        })
+    });
+    println!("Part 1: {}", a.expect("no value of a produces a clock signal"));
 }
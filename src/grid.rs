@@ -0,0 +1,364 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+pub(crate) type Pos = (i32, i32);
+
+/// Largest coordinate `parse_pos` accepts, leaving room to step past it
+/// without overflowing.
+pub(crate) const MAX_COORD: i32 = 1_000_000_000;
+
+/// The four positions next to `pos`, whether or not they can be entered.
+pub(crate) fn adjacent(pos: Pos) -> [Pos; 4] {
+    let (x, y) = pos;
+    [(x + 1, y), (x, y - 1), (x - 1, y), (x, y + 1)]
+}
+
+pub(crate) fn manhattan(a: Pos, b: Pos) -> usize {
+    // Done in i64 so that positions far apart don't overflow.
+    let (x1, y1) = (i64::from(a.0), i64::from(a.1));
+    let (x2, y2) = (i64::from(b.0), i64::from(b.1));
+    ((x1 - x2).abs() + (y1 - y2).abs()) as usize
+}
+
+/// Parses a location written as `X,Y`, where both are from 0 to `MAX_COORD`.
+pub(crate) fn parse_pos(s: &str) -> Result<Pos, String> {
+    let coords = s.split(',')
+        .map(|c| c.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>();
+    match coords.as_ref().map(|c| c.as_slice()) {
+        Ok(&[x, y]) if (0..=MAX_COORD).contains(&x) && (0..=MAX_COORD).contains(&y) => Ok((x, y)),
+        _ => Err(format!("expected a location as X,Y from 0 to {}, found {:?}", MAX_COORD, s)),
+    }
+}
+
+pub(crate) trait Graph {
+    type Node: Clone + Debug + Eq + Hash;
+
+    fn neighbours(&self, node: &Self::Node) -> Vec<Self::Node>;
+
+    /// Cost of moving from `from` to its neighbour `to`.
+    fn cost(&self, _from: &Self::Node, _to: &Self::Node) -> usize {
+        1
+    }
+}
+
+/// A route through a graph, including the start and end nodes.
+#[derive(Debug)]
+pub(crate) struct Path<N> {
+    pub(crate) cost: usize,
+    pub(crate) nodes: Vec<N>,
+    /// Number of nodes whose neighbours were looked at to find the path.
+    pub(crate) expanded: usize,
+}
+
+/// Why a search didn't find a path.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum NotFound {
+    /// Every node that can be reached from the start was searched.
+    Unreachable,
+    /// Paths were given up on at the limit, so a goal may be further away.
+    Limit,
+}
+
+fn reconstruct<N>(came_from: &HashMap<N, N>, start: &N, end: N, cost: usize, expanded: usize) -> Path<N>
+where
+    N: Clone + Eq + Hash,
+{
+    let mut nodes = vec![end];
+    while nodes.last().unwrap() != start {
+        let previous = came_from[nodes.last().unwrap()].clone();
+        nodes.push(previous);
+    }
+    nodes.reverse();
+    Path { cost, nodes, expanded }
+}
+
+/// Path with the fewest steps from `start` to a node where `is_goal` is
+/// true, giving up on paths longer than `max_steps` if there's a limit.
+/// Costs are ignored, so the path's cost is its number of steps.
+pub(crate) fn bfs<G, F>(
+    graph: &G,
+    start: G::Node,
+    is_goal: F,
+    max_steps: Option<usize>,
+) -> Result<Path<G::Node>, NotFound>
+where
+    G: Graph,
+    F: Fn(&G::Node) -> bool,
+{
+    let mut came_from = HashMap::new();
+    let mut queue = VecDeque::new();
+    let mut expanded = 0;
+    let mut not_found = NotFound::Unreachable;
+    queue.push_back((start.clone(), 0));
+
+    while let Some((current, steps)) = queue.pop_front() {
+        if is_goal(&current) {
+            return Ok(reconstruct(&came_from, &start, current, steps, expanded));
+        }
+        if max_steps.is_some_and(|max| steps >= max) {
+            // Everything closer has already been queued, so any neighbour
+            // not seen yet is beyond the limit.
+            let unseen = graph.neighbours(&current)
+                .iter()
+                .any(|n| *n != start && !came_from.contains_key(n));
+            if unseen {
+                not_found = NotFound::Limit;
+            }
+            continue;
+        }
+        expanded += 1;
+        for neighbour in graph.neighbours(&current) {
+            if neighbour != start && !came_from.contains_key(&neighbour) {
+                came_from.insert(neighbour.clone(), current.clone());
+                queue.push_back((neighbour, steps + 1));
+            }
+        }
+    }
+    Err(not_found)
+}
+
+/// Steps from `start` to every node that can be reached in at most
+/// `max_steps`, or every reachable node if there's no limit.
+pub(crate) fn reachable<G: Graph>(graph: &G, start: G::Node, max_steps: Option<usize>) -> HashMap<G::Node, usize> {
+    let mut steps = HashMap::new();
+    let mut queue = VecDeque::new();
+    steps.insert(start.clone(), 0);
+    queue.push_back((start, 0));
+
+    while let Some((current, current_steps)) = queue.pop_front() {
+        if max_steps.is_some_and(|max| current_steps >= max) {
+            continue;
+        }
+        for neighbour in graph.neighbours(&current) {
+            if !steps.contains_key(&neighbour) {
+                steps.insert(neighbour.clone(), current_steps + 1);
+                queue.push_back((neighbour, current_steps + 1));
+            }
+        }
+    }
+    steps
+}
+
+/// Cheapest path from `start` to a node where `is_goal` is true, giving up
+/// on paths that cost more than `max_cost` if there's a limit.
+#[allow(dead_code)]
+pub(crate) fn dijkstra<G, F>(
+    graph: &G,
+    start: G::Node,
+    is_goal: F,
+    max_cost: Option<usize>,
+) -> Result<Path<G::Node>, NotFound>
+where
+    G: Graph,
+    F: Fn(&G::Node) -> bool,
+{
+    a_star(graph, start, is_goal, |_| 0, max_cost)
+}
+
+/// Cheapest path from `start` to a node where `is_goal` is true, guided by
+/// a `heuristic` that must never overestimate the cost left. Paths that
+/// would cost more than `max_cost` are given up on if there's a limit.
+pub(crate) fn a_star<G, F, H>(
+    graph: &G,
+    start: G::Node,
+    is_goal: F,
+    heuristic: H,
+    max_cost: Option<usize>,
+) -> Result<Path<G::Node>, NotFound>
+where
+    G: Graph,
+    F: Fn(&G::Node) -> bool,
+    H: Fn(&G::Node) -> usize,
+{
+    let mut frontier = PriorityQueue::new();
+    let mut came_from = HashMap::new();
+    let mut cost_so_far = HashMap::new();
+    // Neighbours skipped for being over the limit, in case they're never
+    // reached another way.
+    let mut over_limit = HashSet::new();
+    let mut expanded = 0;
+    frontier.put((start.clone(), 0), heuristic(&start));
+    cost_so_far.insert(start.clone(), 0);
+
+    while let Some((current, cost)) = frontier.get() {
+        if cost > cost_so_far[&current] {
+            // A cheaper route to this node was found after it was queued.
+            continue;
+        }
+        if is_goal(&current) {
+            return Ok(reconstruct(&came_from, &start, current, cost, expanded));
+        }
+        expanded += 1;
+
+        for neighbour in graph.neighbours(&current) {
+            let new_cost = cost + graph.cost(&current, &neighbour);
+            if max_cost.is_some_and(|max| new_cost + heuristic(&neighbour) > max) {
+                over_limit.insert(neighbour);
+                continue;
+            }
+            if cost_so_far.get(&neighbour).is_none_or(|&c| new_cost < c) {
+                cost_so_far.insert(neighbour.clone(), new_cost);
+                came_from.insert(neighbour.clone(), current.clone());
+                let priority = new_cost + heuristic(&neighbour);
+                frontier.put((neighbour, new_cost), priority);
+            }
+        }
+    }
+    if over_limit.iter().all(|n| cost_so_far.contains_key(n)) {
+        Err(NotFound::Unreachable)
+    } else {
+        Err(NotFound::Limit)
+    }
+}
+
+pub(crate) struct PriorityQueue<V: Debug, P: Debug + Ord = i32> {
+    heap: BinaryHeap<PriorityItem<V, P>>,
+}
+
+impl<V: Debug, P: Debug + Ord> PriorityQueue<V, P> {
+    pub(crate) fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    pub(crate) fn put(&mut self, item: V, priority: P) {
+        self.heap.push(PriorityItem::new(item, priority));
+    }
+
+    pub(crate) fn get(&mut self) -> Option<V> {
+        self.heap.pop().map(|pi| pi.value)
+    }
+}
+
+#[derive(Debug)]
+struct PriorityItem<V: Debug, P: Debug + Ord> {
+    value: V,
+    priority: P,
+}
+
+impl<V: Debug, P: Debug + Ord> PriorityItem<V, P> {
+    fn new(value: V, priority: P) -> Self {
+        PriorityItem { value, priority }
+    }
+}
+
+impl<V: Debug, P: Debug + Ord> Eq for PriorityItem<V, P> {}
+
+impl<V: Debug, P: Debug + Ord> PartialEq for PriorityItem<V, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl<V: Debug, P: Debug + Ord> PartialOrd for PriorityItem<V, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<V: Debug, P: Debug + Ord> Ord for PriorityItem<V, P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // make this a min heap
+        match self.priority.cmp(&other.priority) {
+            Ordering::Less => Ordering::Greater,
+            Ordering::Equal => Ordering::Equal,
+            Ordering::Greater => Ordering::Less,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An open 10x10 grid where moving right costs 5.
+    struct Field;
+
+    impl Graph for Field {
+        type Node = Pos;
+
+        fn neighbours(&self, node: &Pos) -> Vec<Pos> {
+            adjacent(*node)
+                .iter()
+                .cloned()
+                .filter(|&(x, y)| x >= 0 && y >= 0 && x < 10 && y < 10)
+                .collect()
+        }
+
+        fn cost(&self, from: &Pos, to: &Pos) -> usize {
+            if to.0 > from.0 { 5 } else { 1 }
+        }
+    }
+
+    fn assert_connected(path: &Path<Pos>, start: Pos, goal: Pos) {
+        assert_eq!(path.nodes[0], start);
+        assert_eq!(*path.nodes.last().unwrap(), goal);
+        for pair in path.nodes.windows(2) {
+            assert_eq!(manhattan(pair[0], pair[1]), 1);
+        }
+    }
+
+    #[test]
+    fn test_searches() {
+        let (start, goal) = ((0, 0), (3, 4));
+
+        let path = bfs(&Field, start, |&p| p == goal, None).unwrap();
+        assert_eq!(path.cost, 7);
+        assert_eq!(path.nodes.len(), 8);
+        assert_connected(&path, start, goal);
+
+        let path = dijkstra(&Field, start, |&p| p == goal, None).unwrap();
+        assert_eq!(path.cost, 3 * 5 + 4);
+        assert_connected(&path, start, goal);
+
+        let path = a_star(&Field, start, |&p| p == goal, |&p| manhattan(p, goal), None).unwrap();
+        assert_eq!(path.cost, 3 * 5 + 4);
+        assert_connected(&path, start, goal);
+        assert_eq!(
+            a_star(&Field, start, |&p| p == goal, |&p| manhattan(p, goal), Some(18)).err(),
+            Some(NotFound::Limit)
+        );
+
+        let path = bfs(&Field, start, |&p| p == start, None).unwrap();
+        assert_eq!((path.cost, path.nodes, path.expanded), (0, vec![start], 0));
+        assert_eq!(bfs(&Field, start, |&p| p == (10, 10), None).err(), Some(NotFound::Unreachable));
+        assert_eq!(bfs(&Field, start, |&p| p == goal, Some(6)).err(), Some(NotFound::Limit));
+        // The limit isn't reached when the whole field is within it.
+        assert_eq!(bfs(&Field, start, |&p| p == (10, 10), Some(18)).err(), Some(NotFound::Unreachable));
+        assert_eq!(bfs(&Field, start, |&p| p == (10, 10), Some(17)).err(), Some(NotFound::Limit));
+        assert_eq!(
+            dijkstra(&Field, start, |&p| p == (10, 10), Some(9 * 5 + 9)).err(),
+            Some(NotFound::Unreachable)
+        );
+    }
+
+    #[test]
+    fn test_parse_pos() {
+        assert_eq!(parse_pos("31,39"), Ok((31, 39)));
+        assert_eq!(parse_pos("3, 4"), Ok((3, 4)));
+        assert!(parse_pos("3").is_err());
+        assert!(parse_pos("3,4,5").is_err());
+        assert!(parse_pos("-1,4").is_err());
+        assert!(parse_pos("a,b").is_err());
+        assert_eq!(parse_pos("1000000000,0"), Ok((MAX_COORD, 0)));
+        assert!(parse_pos("2147483647,2147483647").is_err());
+    }
+
+    #[test]
+    fn test_manhattan() {
+        assert_eq!(manhattan((1, 5), (4, 1)), 7);
+        assert_eq!(manhattan((i32::MIN, 0), (i32::MAX, 0)), u32::MAX as usize);
+    }
+
+    #[test]
+    fn test_reachable() {
+        let steps = reachable(&Field, (0, 0), Some(2));
+        assert_eq!(steps.len(), 6);
+        assert_eq!(steps[&(1, 1)], 2);
+        assert_eq!(reachable(&Field, (0, 0), None).len(), 100);
+    }
+}
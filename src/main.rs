@@ -1,3 +1,4 @@
+#![cfg_attr(feature = "nightly", feature(test))]
 #![deny(bare_trait_objects)]
 #![deny(elided_lifetimes_in_paths)]
 #![deny(missing_copy_implementations)]
@@ -10,8 +11,9 @@
 #![deny(unused_qualifications)]
 
 #[macro_use] extern crate nom;
+#[cfg(all(feature = "nightly", test))] extern crate test;
 
-use clap::{Arg, App};
+use clap::{Arg, App, AppSettings};
 
 mod day1;
 mod day2;
@@ -39,6 +41,7 @@ mod day23;
 mod day24;
 mod day25;
 mod assembunny;
+mod grid;
 mod parsers;
 mod util;
 
@@ -46,13 +49,22 @@ fn main() {
     let matches = App::new("aoc")
         .version(clap::crate_version!())
         .author(clap::crate_authors!(", "))
+        .setting(AppSettings::TrailingVarArg)
+        .setting(AppSettings::AllowLeadingHyphen)
         .arg(Arg::with_name("day")
             .required(true)
             .help("Day of the advent calendar"))
+        .arg(Arg::with_name("args")
+            .multiple(true)
+            .allow_hyphen_values(true)
+            .help("Options for the day's solver, see `aoc <day> -- --help`"))
         .get_matches();
 
     let day = clap::value_t!(matches.value_of("day"), u32)
         .unwrap_or_else(|e| e.exit());
+    let args = matches.values_of("args")
+        .map(|values| values.collect::<Vec<_>>())
+        .unwrap_or_default();
 
     match day {
         1 => day1::solve(),
@@ -65,20 +77,20 @@ fn main() {
         8 => day8::solve(),
         9 => day9::solve(),
         10 => day10::solve(),
-        11 => day11::solve(),
+        11 => day11::solve(&args),
         12 => day12::solve(),
-        13 => day13::solve(),
+        13 => day13::solve(&args),
         14 => day14::solve(),
         15 => day15::solve(),
         16 => day16::solve(),
-        17 => day17::solve(),
+        17 => day17::solve(&args),
         18 => day18::solve(),
         19 => day19::solve(),
         20 => day20::solve(),
         21 => day21::solve(),
-        22 => day22::solve(),
-        23 => day23::solve(),
-        24 => day24::solve(),
+        22 => day22::solve(&args),
+        23 => day23::solve(&args),
+        24 => day24::solve(&args),
         25 => day25::solve(),
         day => println!("No solution found for day {}", day),
     }
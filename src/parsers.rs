@@ -14,6 +14,18 @@ named!(pub int32<&str, i32>,
     )
 );
 
+named!(pub int64<&str, i64>,
+    map_res!(
+        recognize!(
+            tuple!(
+                opt!(tag!("-")),
+                call!(digit)
+            )
+        ),
+        FromStr::from_str
+    )
+);
+
 named!(pub uint64<&str, u64>,
     map!(
         recognize!(
@@ -54,4 +66,10 @@ mod tests {
         assert_eq!(int32res!("10"), 10);
         assert_eq!(int32res!("-10"), -10);
     }
+
+    #[test]
+    fn test_int64() {
+        assert_eq!(int64("-3000000000").to_result(), Ok(-3_000_000_000));
+        assert!(int64("9223372036854775808").to_result().is_err());
+    }
 }
@@ -1,3 +1,5 @@
+#[cfg(test)]
+use std::fmt::Debug;
 use std::io::{self, Read};
 
 pub(crate) fn stdin_as_string() -> String {
@@ -6,6 +8,43 @@ pub(crate) fn stdin_as_string() -> String {
     buffer.trim().to_string()
 }
 
+/// Xorshift generator, so generated test cases are the same on every run.
+#[cfg(test)]
+pub(crate) struct Random(u64);
+
+#[cfg(test)]
+impl Random {
+    pub(crate) fn new(seed: u64) -> Self {
+        Random(seed)
+    }
+
+    /// A number in `0..max`.
+    pub(crate) fn below(&mut self, max: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % max
+    }
+}
+
+/// Check that `solve` agrees with `brute_force` on `cases` inputs made by
+/// `generate`.
+#[cfg(test)]
+pub(crate) fn compare_brute_force<T, R, G, S, B>(cases: usize, seed: u64, mut generate: G, solve: S, brute_force: B)
+where
+    T: Debug,
+    R: Debug + PartialEq,
+    G: FnMut(&mut Random) -> T,
+    S: Fn(&T) -> R,
+    B: Fn(&T) -> R,
+{
+    let mut random = Random::new(seed);
+    for _ in 0..cases {
+        let input = generate(&mut random);
+        assert_eq!(solve(&input), brute_force(&input), "input: {:?}", input);
+    }
+}
+
 //pub fn stdin_lines() -> Vec<String> {
 //    let stdin = io::stdin();
 //    let lines = stdin.lock().lines().map(|l| l.unwrap()).collect();